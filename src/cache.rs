@@ -7,11 +7,85 @@ use std::{
 use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct GitSubmodule {
+    pub path: String,
+    pub commit: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GitDependency {
     pub name: String,
     pub url: String,
     pub revision: String,
+    #[serde(default)]
+    pub commit: Option<String>,
+    #[serde(default)]
+    pub submodules: Option<Vec<GitSubmodule>>,
+    #[serde(default)]
+    pub patches: Vec<String>,
+    #[serde(default)]
+    pub head: Option<String>,
+    #[serde(default)]
+    pub override_path: Option<PathBuf>,
+    #[serde(default)]
+    pub vendor_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct ArchiveDependency {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+    #[serde(default)]
+    pub strip_components: u32,
+    #[serde(default)]
+    pub patches: Vec<String>,
+    #[serde(default)]
+    pub override_path: Option<PathBuf>,
+    #[serde(default)]
+    pub vendor_path: Option<PathBuf>,
+}
+
+pub const DEFAULT_COMPONENT: &str = "default";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum InstallEntry {
+    Copy(PathBuf),
+    File {
+        source: PathBuf,
+        #[serde(default)]
+        mode: Option<u32>,
+        #[serde(default)]
+        component: Option<String>,
+    },
+    Symlink {
+        symlink: PathBuf,
+        #[serde(default)]
+        component: Option<String>,
+    },
+}
+
+impl InstallEntry {
+    pub fn component(&self) -> &str {
+        match self {
+            InstallEntry::Copy(_) => DEFAULT_COMPONENT,
+            InstallEntry::File { component, .. } | InstallEntry::Symlink { component, .. } => component.as_deref().unwrap_or(DEFAULT_COMPONENT),
+        }
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        match self {
+            InstallEntry::Copy(source) | InstallEntry::File { source, .. } => Some(source),
+            InstallEntry::Symlink { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Tool {
+    pub path: PathBuf,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,15 +95,31 @@ pub struct FabricateCache {
 
     version: i64,
 
+    #[serde(default)]
+    pub project_dir: PathBuf,
+    #[serde(default)]
+    pub config_file: Option<PathBuf>,
     pub prefix: String,
+    #[serde(default)]
+    pub install_dirs: HashMap<String, PathBuf>,
     pub options: HashMap<String, String>,
-    pub installs: HashMap<PathBuf, PathBuf>,
+    #[serde(default)]
+    pub cross_file: Option<PathBuf>,
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    pub installs: HashMap<PathBuf, InstallEntry>,
     pub git_dependencies: Vec<GitDependency>,
+    #[serde(default)]
+    pub archive_dependencies: Vec<ArchiveDependency>,
+    #[serde(default)]
+    pub tools: Vec<Tool>,
 }
 
+pub const CACHE_FILE: &str = "fabricate_cache.toml";
+
 const CURRENT_VERSION: i64 = 1;
 
-fn get_version(cache_data: &String) -> Result<i64> {
+fn get_version(cache_data: &str) -> Result<i64> {
     let cache = toml::from_str::<toml::Table>(cache_data).context("Failed to parse fabricate cache")?;
 
     match cache.get("version") {
@@ -40,14 +130,28 @@ fn get_version(cache_data: &String) -> Result<i64> {
 }
 
 impl FabricateCache {
-    pub fn new(path: &Path, prefix: String, options: HashMap<String, String>, installs: HashMap<PathBuf, PathBuf>, git_dependencies: Vec<GitDependency>) -> FabricateCache {
+    pub fn new(path: &Path, project_dir: PathBuf, prefix: String) -> FabricateCache {
         FabricateCache {
             path: path.to_path_buf(),
             version: CURRENT_VERSION,
+            project_dir,
+            config_file: None,
             prefix,
-            options,
-            installs,
-            git_dependencies,
+            install_dirs: HashMap::new(),
+            options: HashMap::new(),
+            cross_file: None,
+            environment: HashMap::new(),
+            installs: HashMap::new(),
+            git_dependencies: Vec::new(),
+            archive_dependencies: Vec::new(),
+            tools: Vec::new(),
+        }
+    }
+
+    pub fn open(build_dir: &Path) -> Result<FabricateCache> {
+        match FabricateCache::load(&build_dir.join(CACHE_FILE))? {
+            None => bail!("Cache is not initialized, make sure the build directory path is correct"),
+            Some(cache) => Ok(cache),
         }
     }
 
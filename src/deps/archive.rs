@@ -0,0 +1,388 @@
+use std::{
+    fs::{File, copy, create_dir_all, exists, hard_link, read, remove_dir_all, remove_file, rename, set_permissions, symlink_metadata},
+    io::{Read, copy as io_copy},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use git2::{ApplyLocation, Diff, Repository};
+use tar::{Archive, EntryType};
+use ureq::{
+    Agent,
+    tls::{RootCerts, TlsConfig},
+};
+use xz2::read::XzDecoder;
+use zip::ZipArchive;
+
+use crate::deps::{is_local_url, sha256_file};
+
+#[derive(Clone, Copy)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    TarXz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub fn from_name(name: &str) -> Option<ArchiveFormat> {
+        match name {
+            "tar" => Some(ArchiveFormat::Tar),
+            "tar.gz" => Some(ArchiveFormat::TarGz),
+            "tar.xz" => Some(ArchiveFormat::TarXz),
+            "zip" => Some(ArchiveFormat::Zip),
+            _ => None,
+        }
+    }
+
+    pub fn from_url(url: &str) -> Option<ArchiveFormat> {
+        let path = url.split(['?', '#']).next().unwrap_or(url).to_lowercase();
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if path.ends_with(".tar.xz") || path.ends_with(".txz") {
+            Some(ArchiveFormat::TarXz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveFormat::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn is_sha256(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn download(url: &str, dest: &Path) -> Result<()> {
+    if is_local_url(url) {
+        let path = url.strip_prefix("file://").unwrap_or(url);
+        copy(path, dest).with_context(|| format!("Failed to copy `{}`", path))?;
+        return Ok(());
+    }
+
+    let agent: Agent = Agent::config_builder().tls_config(TlsConfig::builder().root_certs(RootCerts::PlatformVerifier).build()).build().into();
+    let response = agent.get(url).call().with_context(|| format!("Failed to download `{}`", url))?;
+
+    let mut file = File::create(dest).context("Failed to create download file")?;
+    io_copy(&mut response.into_body().into_reader(), &mut file).with_context(|| format!("Failed to download `{}`", url))?;
+    Ok(())
+}
+
+fn verify(path: &Path, sha256: &str) -> Result<()> {
+    let hash = sha256_file(path).context("Failed to hash downloaded archive")?;
+    if hash != sha256.to_lowercase() {
+        bail!("Checksum mismatch, expected sha256 `{}` but the archive has `{}`", sha256.to_lowercase(), hash);
+    }
+    Ok(())
+}
+
+fn strip_path(path: &Path, strip_components: u32) -> Result<Option<PathBuf>> {
+    let mut stripped = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(part) => stripped.push(part),
+            _ => bail!("Archive entry `{}` escapes the extraction directory", path.to_string_lossy()),
+        }
+    }
+
+    let stripped: PathBuf = stripped.components().skip(strip_components as usize).collect();
+    if stripped.as_os_str().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(stripped))
+}
+
+// Symlinks extracted by earlier entries must not redirect later entries outside of the extraction directory
+fn check_symlinks(dest: &Path, path: &Path) -> Result<()> {
+    let mut current = dest.to_path_buf();
+    for component in path.parent().into_iter().flat_map(|parent| parent.components()) {
+        current.push(component);
+        if symlink_metadata(&current).is_ok_and(|metadata| metadata.is_symlink()) {
+            bail!("Archive entry `{}` is extracted through the symlink `{}`", path.to_string_lossy(), current.to_string_lossy());
+        }
+    }
+    Ok(())
+}
+
+fn extract_tar(reader: impl Read, dest: &Path, strip_components: u32) -> Result<()> {
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries().context("Failed to read archive")? {
+        let mut entry = entry.context("Failed to read archive entry")?;
+
+        let path = match strip_path(&entry.path().context("Invalid archive entry path")?, strip_components)? {
+            None => continue,
+            Some(path) => {
+                check_symlinks(dest, &path)?;
+                dest.join(path)
+            }
+        };
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).context("Failed to create directory")?;
+        }
+
+        if entry.header().entry_type() == EntryType::Link {
+            let target = match entry.link_name().context("Invalid archive link target")? {
+                None => bail!("Archive hard link `{}` has no target", path.to_string_lossy()),
+                Some(target) => target,
+            };
+
+            match strip_path(&target, strip_components)? {
+                None => bail!("Archive hard link `{}` points outside the extracted files", path.to_string_lossy()),
+                Some(target) => {
+                    check_symlinks(dest, &target)?;
+                    hard_link(dest.join(target), &path).with_context(|| format!("Failed to extract `{}`", path.to_string_lossy()))?
+                }
+            }
+            continue;
+        }
+
+        entry.unpack(&path).with_context(|| format!("Failed to extract `{}`", path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+fn extract_zip(file: File, dest: &Path, strip_components: u32) -> Result<()> {
+    let mut archive = ZipArchive::new(file).context("Failed to read archive")?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).context("Failed to read archive entry")?;
+
+        let path = match file.enclosed_name() {
+            None => bail!("Archive entry `{}` escapes the extraction directory", String::from_utf8_lossy(file.name_raw())),
+            Some(path) => match strip_path(&path, strip_components)? {
+                None => continue,
+                Some(path) => dest.join(path),
+            },
+        };
+
+        if file.is_dir() {
+            create_dir_all(&path).context("Failed to create directory")?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).context("Failed to create directory")?;
+        }
+
+        let mut out = File::create(&path).with_context(|| format!("Failed to extract `{}`", path.to_string_lossy()))?;
+        io_copy(&mut file, &mut out).with_context(|| format!("Failed to extract `{}`", path.to_string_lossy()))?;
+
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            set_permissions(&path, PermissionsExt::from_mode(mode & 0o7777)).context("Failed to set permissions")?;
+        }
+    }
+    Ok(())
+}
+
+fn extract(archive_path: &Path, format: ArchiveFormat, dest: &Path, strip_components: u32) -> Result<()> {
+    let file = File::open(archive_path).context("Failed to open downloaded archive")?;
+    match format {
+        ArchiveFormat::Tar => extract_tar(file, dest, strip_components),
+        ArchiveFormat::TarGz => extract_tar(GzDecoder::new(file), dest, strip_components),
+        ArchiveFormat::TarXz => extract_tar(XzDecoder::new(file), dest, strip_components),
+        ArchiveFormat::Zip => extract_zip(file, dest, strip_components),
+    }
+}
+
+// Patches are applied with libgit2 like the patches of git dependencies, through a repository kept
+// outside of the extracted tree
+fn apply_patches(dir: &Path, patches: &[PathBuf]) -> Result<()> {
+    if patches.is_empty() {
+        return Ok(());
+    }
+
+    let git_dir = dir.with_file_name(format!(".{}.git", dir.file_name().unwrap_or_default().to_string_lossy()));
+    let result = (|| {
+        let repo = Repository::init_bare(&git_dir).context("Failed to initialize repository to apply patches")?;
+        repo.set_workdir(dir, false).context("Failed to initialize repository to apply patches")?;
+
+        for patch in patches {
+            let name = patch.file_name().unwrap_or(patch.as_os_str()).to_string_lossy();
+
+            let patch_data = read(patch).with_context(|| format!("Failed to read patch `{}`", patch.to_string_lossy()))?;
+            let diff = Diff::from_buffer(&patch_data).with_context(|| format!("Failed to parse patch `{}`", name))?;
+            repo.apply(&diff, ApplyLocation::WorkDir, None).with_context(|| format!("Failed to apply patch `{}`", name))?;
+        }
+        Ok(())
+    })();
+
+    remove_dir_all(&git_dir).context("Failed to remove patch repository")?;
+    result
+}
+
+pub fn fetch(url: &str, sha256: &str, format: ArchiveFormat, strip_components: u32, patches: &[PathBuf], archive_path: &Path) -> Result<()> {
+    let file_name = match archive_path.file_name() {
+        None => bail!("Failed to resolve archive name"),
+        Some(file_name) => file_name.to_string_lossy(),
+    };
+
+    let download_path = archive_path.with_file_name(format!(".{}.download", file_name));
+    let extract_path = archive_path.with_file_name(format!(".{}.extract", file_name));
+
+    for path in [&download_path, &extract_path] {
+        if exists(path)? {
+            remove_dir_all(path).or_else(|_| remove_file(path)).context("Failed to remove leftovers of a previous download")?;
+        }
+    }
+
+    if let Some(parent) = archive_path.parent() {
+        create_dir_all(parent).context("Failed to create archives directory")?;
+    }
+
+    download(url, &download_path)?;
+    if let Err(err) = verify(&download_path, sha256) {
+        remove_file(&download_path).context("Failed to remove downloaded archive")?;
+        return Err(err);
+    }
+
+    create_dir_all(&extract_path).context("Failed to create extraction directory")?;
+    extract(&download_path, format, &extract_path, strip_components)?;
+    remove_file(&download_path).context("Failed to remove downloaded archive")?;
+
+    apply_patches(&extract_path, patches)?;
+
+    if exists(archive_path)? {
+        remove_dir_all(archive_path).context("Failed to remove outdated archive")?;
+    }
+    rename(&extract_path, archive_path).context("Failed to move extracted archive")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir_all, read_dir, read_to_string, write},
+        path::{Path, PathBuf},
+    };
+
+    use tar::{Builder, EntryType, Header};
+
+    use super::{apply_patches, extract_tar, strip_path};
+    use crate::testing::TestDir;
+
+    enum TestEntry<'a> {
+        File(&'a str, &'a str),
+        Symlink(&'a str, &'a str),
+        HardLink(&'a str, &'a str),
+    }
+
+    // The names are written into the header directly, the tar builder refuses the escaping paths under test
+    fn header(path: &str, entry_type: EntryType, size: u64) -> Header {
+        let mut header = Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(size);
+        header
+    }
+
+    fn archive(entries: &[TestEntry]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for entry in entries {
+            match entry {
+                TestEntry::File(path, content) => {
+                    let mut header = header(path, EntryType::Regular, content.len() as u64);
+                    header.set_cksum();
+                    builder.append(&header, content.as_bytes()).unwrap();
+                }
+                TestEntry::Symlink(path, target) | TestEntry::HardLink(path, target) => {
+                    let entry_type = if matches!(entry, TestEntry::Symlink(..)) { EntryType::Symlink } else { EntryType::Link };
+                    let mut header = header(path, entry_type, 0);
+                    header.as_gnu_mut().unwrap().linkname[..target.len()].copy_from_slice(target.as_bytes());
+                    header.set_cksum();
+                    builder.append(&header, &[][..]).unwrap();
+                }
+            }
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn extract_dir(name: &str) -> TestDir {
+        let dir = TestDir::new(name);
+        create_dir_all(dir.join("dest")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn strip_path_components() {
+        assert_eq!(strip_path(Path::new("pkg/src/main.c"), 0).unwrap(), Some(PathBuf::from("pkg/src/main.c")));
+        assert_eq!(strip_path(Path::new("pkg/src/main.c"), 1).unwrap(), Some(PathBuf::from("src/main.c")));
+        assert_eq!(strip_path(Path::new("./pkg/./main.c"), 1).unwrap(), Some(PathBuf::from("main.c")));
+        assert_eq!(strip_path(Path::new("pkg/"), 1).unwrap(), None);
+        assert_eq!(strip_path(Path::new("pkg/main.c"), 3).unwrap(), None);
+
+        assert!(strip_path(Path::new("../main.c"), 0).is_err());
+        assert!(strip_path(Path::new("pkg/../../main.c"), 1).is_err());
+        assert!(strip_path(Path::new("/etc/passwd"), 0).is_err());
+    }
+
+    #[test]
+    fn extract_tar_strips_components() {
+        let dir = extract_dir("extract-strip");
+        let dest = dir.join("dest");
+        let data = archive(&[
+            TestEntry::File("pkg/src/main.c", "int main;"),
+            TestEntry::Symlink("pkg/src/link.c", "main.c"),
+            TestEntry::HardLink("pkg/src/hard.c", "pkg/src/main.c"),
+        ]);
+
+        extract_tar(data.as_slice(), &dest, 1).unwrap();
+        assert_eq!(read_to_string(dest.join("src/main.c")).unwrap(), "int main;");
+        assert_eq!(read_to_string(dest.join("src/link.c")).unwrap(), "int main;");
+        assert_eq!(read_to_string(dest.join("src/hard.c")).unwrap(), "int main;");
+    }
+
+    #[test]
+    fn extract_tar_rejects_escapes() {
+        let cases: [(&str, &[TestEntry]); 5] = [
+            ("parent", &[TestEntry::File("pkg/../../escaped", "x")]),
+            ("absolute", &[TestEntry::File("/escaped", "x")]),
+            ("symlink-dir", &[TestEntry::Symlink("pkg/link", "../.."), TestEntry::File("pkg/link/escaped", "x")]),
+            ("hard-link", &[TestEntry::HardLink("pkg/hard", "../outside")]),
+            ("hard-link-symlink", &[TestEntry::Symlink("pkg/link", "../.."), TestEntry::HardLink("pkg/hard", "pkg/link/outside")]),
+        ];
+
+        for (name, entries) in cases {
+            let dir = extract_dir(&format!("extract-{}", name));
+            write(dir.join("outside"), "outside").unwrap();
+
+            assert!(extract_tar(archive(entries).as_slice(), &dir.join("dest"), 0).is_err(), "{} should fail", name);
+            assert!(!dir.join("escaped").exists(), "{} escaped the extraction directory", name);
+            assert!(!dir.join("dest/pkg/hard").exists(), "{} linked a file outside the extraction directory", name);
+        }
+    }
+
+    #[test]
+    fn apply_patches_to_extracted_files() {
+        let dir = extract_dir("patches");
+        let dest = dir.join("dest");
+        write(dest.join("main.c"), "int main;\n").unwrap();
+        write(
+            dir.join("0001-fix.patch"),
+            "diff --git a/main.c b/main.c\n--- a/main.c\n+++ b/main.c\n@@ -1 +1 @@\n-int main;\n+int main(void);\n",
+        )
+        .unwrap();
+        write(
+            dir.join("0002-broken.patch"),
+            "diff --git a/main.c b/main.c\n--- a/main.c\n+++ b/main.c\n@@ -1 +1 @@\n-int start;\n+int start(void);\n",
+        )
+        .unwrap();
+
+        apply_patches(&dest, &[dir.join("0001-fix.patch")]).unwrap();
+        assert_eq!(read_to_string(dest.join("main.c")).unwrap(), "int main(void);\n");
+        assert!(apply_patches(&dest, &[dir.join("0002-broken.patch")]).is_err());
+        assert_eq!(read_to_string(dest.join("main.c")).unwrap(), "int main(void);\n");
+
+        for (path, count) in [(&*dir, 3), (dest.as_path(), 1)] {
+            let entries: Vec<_> = read_dir(path).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+            assert_eq!(entries.len(), count, "the patch repository was left behind: {:?}", entries);
+        }
+    }
+}
@@ -0,0 +1,627 @@
+use std::{
+    fs::{create_dir_all, exists, read, remove_dir_all},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::{Context, Result, bail};
+use git2::{ApplyLocation, AutotagOption, BranchType, Diff, Direction, FetchOptions, Oid, Remote, RemoteHead, Repository, Signature, StatusOptions, SubmoduleUpdateOptions, build::CheckoutBuilder};
+
+use crate::{
+    cache::GitSubmodule,
+    deps::{is_local_url, mirrors::Mirrors},
+};
+
+const MAX_LISTED_CHANGES: usize = 10;
+const PATCH_AUTHOR: &str = "Fabricate";
+const PATCH_EMAIL: &str = "fabricate@localhost";
+
+#[derive(Default)]
+pub struct GitOptions {
+    pub depth: Option<u32>,
+    pub submodules: bool,
+    pub patches: Vec<PathBuf>,
+    pub offline: bool,
+    pub mirrors: Rc<Mirrors>,
+}
+
+pub struct Checkout {
+    pub commit: String,
+    pub head: String,
+}
+
+fn is_commit_hash(revision: &str) -> bool {
+    revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn is_abbreviated_hash(revision: &str) -> bool {
+    (4..40).contains(&revision.len()) && revision.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn find_remote_ref(heads: &[RemoteHead], revision: &str) -> Option<(String, Oid)> {
+    let candidates = [
+        (format!("refs/tags/{}^{{}}", revision), format!("refs/tags/{}", revision)),
+        (format!("refs/tags/{}", revision), format!("refs/tags/{}", revision)),
+        (format!("refs/heads/{}", revision), format!("refs/heads/{}", revision)),
+        (revision.to_string(), revision.to_string()),
+    ];
+
+    for (candidate, name) in candidates {
+        if let Some(head) = heads.iter().find(|head| head.name() == candidate) {
+            return Some((name, head.oid()));
+        }
+    }
+
+    None
+}
+
+fn list_remote_ref(remote: &mut Remote, url: &str, revision: &str) -> Result<Option<(String, Oid)>> {
+    remote.connect(Direction::Fetch).with_context(|| format!("Failed to connect to `{}`", url))?;
+    let found = find_remote_ref(remote.list().context("Failed to list remote references")?, revision);
+    remote.disconnect().context("Failed to disconnect from remote")?;
+    Ok(found)
+}
+
+/// Resolves the commit a revision currently points to, or `None` when the revision is a commit hash.
+pub fn resolve_remote(url: &str, revision: &str) -> Result<Option<String>> {
+    if is_commit_hash(revision) {
+        return Ok(None);
+    }
+
+    let mut remote = Remote::create_detached(url).context("Failed to create remote")?;
+    match list_remote_ref(&mut remote, url, revision)? {
+        Some((_, oid)) => Ok(Some(oid.to_string())),
+        None if is_abbreviated_hash(revision) => Ok(None),
+        None => bail!("Revision `{}` not found on remote `{}`", revision, url),
+    }
+}
+
+fn fetch_all(repo: &Repository, remote: &mut Remote) -> Result<()> {
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.download_tags(AutotagOption::None);
+    if repo.is_shallow() {
+        fetch_options.depth(i32::MAX);
+    }
+
+    remote
+        .fetch(&["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"], Some(&mut fetch_options), None)
+        .context("Git fetch failed")
+}
+
+fn revparse_commit(repo: &Repository, revision: &str) -> Option<Oid> {
+    repo.revparse_single(revision).and_then(|object| object.peel_to_commit()).map(|commit| commit.id()).ok()
+}
+
+fn fetch_refspec(name: &str) -> String {
+    match name.strip_prefix("refs/heads/") {
+        Some(branch) => format!("+{}:refs/remotes/origin/{}", name, branch),
+        None if name.starts_with("refs/tags/") => format!("+{}:{}", name, name),
+        None => name.to_string(),
+    }
+}
+
+fn fetch_revision(repo: &Repository, url: &str, revision: &str, locked_commit: Option<&str>, options: &GitOptions) -> Result<Oid> {
+    let pinned = match locked_commit {
+        Some(locked_commit) => Some(Oid::from_str(locked_commit).context("Invalid locked commit")?),
+        None if is_commit_hash(revision) => Some(Oid::from_str(revision).context("Invalid commit")?),
+        None => None,
+    };
+
+    if let Some(pinned) = pinned
+        && repo.find_commit(pinned).is_ok()
+    {
+        return Ok(pinned);
+    }
+
+    if options.offline && !is_local_url(url) {
+        // Fall back to refs fetched by previous setups
+        if pinned.is_none()
+            && let Some(oid) = revparse_commit(repo, &format!("origin/{}", revision))
+                .or_else(|| revparse_commit(repo, &format!("refs/tags/{}", revision)))
+                .or_else(|| if is_abbreviated_hash(revision) { revparse_commit(repo, revision) } else { None })
+        {
+            return Ok(oid);
+        }
+
+        bail!("Revision `{}` is not available locally and `--offline` prevents fetching it from `{}`", revision, url);
+    }
+
+    let mut remote = repo.find_remote("origin").context("Failed to find remote")?;
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.download_tags(AutotagOption::None);
+    // The local transport does not support shallow fetches, local clones are cheap anyway
+    if let Some(depth) = options.depth
+        && !is_local_url(url)
+    {
+        fetch_options.depth(i32::try_from(depth).with_context(|| format!("Clone depth `{}` is out of range", depth))?);
+    }
+
+    // Fetch the ref named by the revision, a pinned commit is usually part of its history
+    let mut fetched = None;
+    if !is_commit_hash(revision) {
+        match list_remote_ref(&mut remote, url, revision)? {
+            Some((name, oid)) => {
+                remote.fetch(&[fetch_refspec(&name)], Some(&mut fetch_options), None).context("Git fetch failed")?;
+                fetched = Some(oid);
+            }
+            // An abbreviated commit hash can only be resolved within the complete history
+            None if is_abbreviated_hash(revision) => {
+                if pinned.is_none() {
+                    fetch_all(repo, &mut remote)?;
+                    let oid = revparse_commit(repo, revision).with_context(|| format!("Revision `{}` not found on remote `{}`", revision, url))?;
+                    return Ok(oid);
+                }
+            }
+            None => bail!("Revision `{}` not found on remote `{}`", revision, url),
+        }
+    }
+
+    let pinned = match pinned {
+        None => return fetched.context("Failed to resolve revision"),
+        Some(pinned) => pinned,
+    };
+
+    if repo.find_commit(pinned).is_ok() {
+        return Ok(pinned);
+    }
+
+    // Most servers refuse to serve arbitrary commits, fall back to the complete history
+    if remote.fetch(&[pinned.to_string()], Some(&mut fetch_options), None).is_err() {
+        fetch_all(repo, &mut remote)?;
+    }
+
+    if repo.find_commit(pinned).is_err() {
+        bail!("Commit `{}` is not available from `{}`", pinned, url);
+    }
+
+    Ok(pinned)
+}
+
+fn prepare(repo: &Repository, url: &str, revision: &str, locked_commit: Option<&str>, options: &GitOptions) -> Result<Checkout> {
+    let oid = fetch_revision(repo, url, revision, locked_commit, options)?;
+
+    // The submodules of the previous checkout are still listed before checking out the new revision
+    if !options.submodules {
+        deinit_submodules(repo)?;
+    }
+
+    let commit = checkout(repo, oid)?;
+
+    if options.submodules {
+        update_submodules(repo, options)?;
+    }
+
+    // Leave the unpatched checkout behind instead of a partially patched one
+    if let Err(err) = apply_patches(repo, &options.patches) {
+        checkout(repo, oid)?;
+        return Err(err);
+    }
+
+    let head = repo.head().and_then(|head| head.peel_to_commit()).context("Failed to resolve HEAD")?;
+    Ok(Checkout { commit, head: head.id().to_string() })
+}
+
+pub fn clone(url: &str, revision: &str, locked_commit: Option<&str>, repo_path: &Path, options: &GitOptions) -> Result<Checkout> {
+    if options.offline && !is_local_url(url) {
+        bail!("Repository is not available locally and `--offline` prevents cloning it from `{}`", url);
+    }
+
+    if exists(repo_path)? {
+        remove_dir_all(repo_path).context("Failed to remove outdated repository")?;
+    }
+
+    let repo = Repository::init(repo_path).context("Failed to initialize repository")?;
+    repo.remote("origin", url).context("Failed to create remote")?;
+
+    // A fresh clone has nothing worth keeping, do not leave a broken repository behind
+    prepare(&repo, url, revision, locked_commit, options).inspect_err(|_| {
+        let _ = remove_dir_all(repo_path);
+    })
+}
+
+pub fn update(url: &str, revision: &str, locked_commit: Option<&str>, repo_path: &Path, expected_commit: Option<&str>, force: bool, options: &GitOptions) -> Result<Checkout> {
+    let repo = match Repository::open(repo_path) {
+        Ok(repo) => repo,
+        Err(_) if force => return clone(url, revision, locked_commit, repo_path, options),
+        Err(err) => bail!(
+            "`{}` is not a git repository ({}), remove it or pass `--force-deps` to replace it",
+            repo_path.to_string_lossy(),
+            err.message()
+        ),
+    };
+
+    if !force {
+        ensure_unmodified(&repo, expected_commit)?;
+    }
+
+    match repo.find_remote("origin") {
+        Ok(remote) if remote.url() == Some(url) => {}
+        Ok(_) => repo.remote_set_url("origin", url).context("Failed to update remote url")?,
+        Err(_) => {
+            repo.remote("origin", url).context("Failed to create remote")?;
+        }
+    }
+
+    prepare(&repo, url, revision, locked_commit, options)
+}
+
+fn is_upstream(repo: &Repository, oid: Oid) -> bool {
+    let references = match repo.references() {
+        Err(_) => return false,
+        Ok(references) => references,
+    };
+
+    for reference in references.flatten() {
+        if !reference.name().is_some_and(|name| name.starts_with("refs/remotes/") || name.starts_with("refs/tags/")) {
+            continue;
+        }
+
+        if let Ok(commit) = reference.peel_to_commit()
+            && (commit.id() == oid || repo.graph_descendant_of(commit.id(), oid).unwrap_or(false))
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+// A checkout that moved from `head` to a different upstream commit was reset by a failed preparation
+pub fn moved_upstream(repo_path: &Path, head: &str) -> bool {
+    let repo = match Repository::open(repo_path) {
+        Err(_) => return true,
+        Ok(repo) => repo,
+    };
+
+    match repo.head().and_then(|head| head.peel_to_commit()) {
+        Err(_) => true,
+        Ok(commit) => commit.id().to_string() != head && is_upstream(&repo, commit.id()),
+    }
+}
+
+// Skips the commits created by `apply_patches` on top of the upstream commit they were applied to
+fn unpatched(repo: &Repository, oid: Oid) -> Oid {
+    let mut oid = oid;
+    while let Ok(commit) = repo.find_commit(oid)
+        && commit.author().name() == Some(PATCH_AUTHOR)
+        && commit.author().email() == Some(PATCH_EMAIL)
+        && let Ok(parent) = commit.parent_id(0)
+    {
+        oid = parent;
+    }
+    oid
+}
+
+pub enum CheckoutState {
+    Clean,
+    Modified(Vec<String>),
+    Moved(String),
+    LocalBranch(String),
+}
+
+fn checkout_state(repo: &Repository, expected_commit: Option<&str>) -> Result<CheckoutState> {
+    let mut status_options = StatusOptions::new();
+    status_options.include_untracked(true).recurse_untracked_dirs(true).include_ignored(false);
+
+    let statuses = repo.statuses(Some(&mut status_options)).context("Failed to read repository status")?;
+    if !statuses.is_empty() {
+        return Ok(CheckoutState::Modified(statuses.iter().filter_map(|entry| entry.path().map(String::from)).collect()));
+    }
+
+    // Without a recorded commit only a HEAD that is part of the remote history is known to be safe to replace
+    let head = repo.head().and_then(|head| head.peel_to_commit()).context("Failed to resolve HEAD of the checkout")?;
+    if expected_commit.is_none_or(|expected_commit| head.id().to_string() != expected_commit) && !is_upstream(repo, unpatched(repo, head.id())) {
+        return Ok(CheckoutState::Moved(head.id().to_string()));
+    }
+
+    // Local branches can hold commits while HEAD is detached at the expected commit
+    for (branch, _) in repo.branches(Some(BranchType::Local)).context("Failed to list local branches")?.flatten() {
+        if let Ok(commit) = branch.get().peel_to_commit()
+            && commit.id() != head.id()
+            && !is_upstream(repo, unpatched(repo, commit.id()))
+        {
+            let name = branch.name().ok().flatten().unwrap_or_default().to_string();
+            return Ok(CheckoutState::LocalBranch(name));
+        }
+    }
+
+    Ok(CheckoutState::Clean)
+}
+
+pub fn state(repo_path: &Path, expected_commit: Option<&str>) -> Result<CheckoutState> {
+    let repo = Repository::open(repo_path).context("Failed to open repository")?;
+    checkout_state(&repo, expected_commit)
+}
+
+fn ensure_unmodified(repo: &Repository, expected_commit: Option<&str>) -> Result<()> {
+    match checkout_state(repo, expected_commit)? {
+        CheckoutState::Clean => Ok(()),
+        CheckoutState::Modified(mut changes) => {
+            if changes.len() > MAX_LISTED_CHANGES {
+                let remaining = changes.len() - MAX_LISTED_CHANGES;
+                changes.truncate(MAX_LISTED_CHANGES);
+                changes.push(format!("and {} more", remaining));
+            }
+
+            bail!(
+                "the checkout has uncommitted changes ({}), commit or discard them or pass `--force-deps` to discard them",
+                changes.join(", ")
+            )
+        }
+        CheckoutState::Moved(head) => match expected_commit {
+            Some(expected_commit) => bail!(
+                "the checkout is at commit `{}` instead of the recorded commit `{}` and may contain local commits, move them elsewhere or pass `--force-deps` to discard them",
+                head,
+                expected_commit
+            ),
+            None => bail!(
+                "the checkout is at commit `{}` which is not part of the remote history and may contain local commits, move them elsewhere or pass `--force-deps` to discard them",
+                head
+            ),
+        },
+        CheckoutState::LocalBranch(branch) => bail!(
+            "the local branch `{}` of the checkout has commits that are not part of the remote history, move them elsewhere or pass `--force-deps` to discard them",
+            branch
+        ),
+    }
+}
+
+fn checkout(repo: &Repository, oid: Oid) -> Result<String> {
+    let commit = repo.find_commit(oid).with_context(|| format!("Failed to find commit `{}`", oid))?;
+
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force().remove_untracked(true)))
+        .context("Failed to checkout revision")?;
+    repo.set_head_detached(commit.id()).context("Failed to set head")?;
+
+    Ok(commit.id().to_string())
+}
+
+fn apply_patches(repo: &Repository, patches: &[PathBuf]) -> Result<()> {
+    let signature = Signature::now(PATCH_AUTHOR, PATCH_EMAIL).context("Failed to create signature")?;
+
+    for patch in patches {
+        let name = patch.file_name().unwrap_or(patch.as_os_str()).to_string_lossy();
+
+        let patch_data = read(patch).with_context(|| format!("Failed to read patch `{}`", patch.to_string_lossy()))?;
+        let diff = Diff::from_buffer(&patch_data).with_context(|| format!("Failed to parse patch `{}`", name))?;
+        repo.apply(&diff, ApplyLocation::Both, None).with_context(|| format!("Failed to apply patch `{}`", name))?;
+
+        // Commit the patch so the checkout stays clean and the patched state has a commit to compare against
+        let tree = repo
+            .index()
+            .and_then(|mut index| index.write_tree())
+            .and_then(|oid| repo.find_tree(oid))
+            .context("Failed to write patched tree")?;
+        let parent = repo.head().and_then(|head| head.peel_to_commit()).context("Failed to resolve HEAD")?;
+        let oid = repo
+            .commit(None, &signature, &signature, &format!("Apply {}", name), &tree, &[&parent])
+            .with_context(|| format!("Failed to commit patch `{}`", name))?;
+        repo.set_head_detached(oid).context("Failed to set head")?;
+    }
+
+    Ok(())
+}
+
+// Submodule URLs go through the mirrors like the dependency URL, the rewritten URL is only stored in the
+// repository config and leaves `.gitmodules` untouched
+fn update_submodules(repo: &Repository, options: &GitOptions) -> Result<()> {
+    for mut submodule in repo.submodules().context("Failed to list submodules")? {
+        let path = submodule.path().to_string_lossy().to_string();
+        let name = submodule.name().with_context(|| format!("Submodule `{}` has an invalid name", path))?.to_string();
+
+        // Initializing again resolves the url from `.gitmodules`, which drops the mirror of an earlier setup
+        submodule.init(true).with_context(|| format!("Failed to initialize submodule `{}`", path))?;
+        let url_key = format!("submodule.{}.url", name);
+        let mut config = repo.config().context("Failed to open repository config")?;
+        let url = config.get_string(&url_key).with_context(|| format!("Submodule `{}` has no url", path))?;
+        let fetch_url = options.mirrors.rewrite(&url);
+        config.set_str(&url_key, &fetch_url).with_context(|| format!("Failed to set url of submodule `{}`", path))?;
+
+        match submodule.open() {
+            Ok(submodule_repo) => submodule_repo
+                .remote_set_url("origin", &fetch_url)
+                .with_context(|| format!("Failed to set url of submodule `{}`", path))?,
+            Err(_) if options.offline && !is_local_url(&fetch_url) => {
+                bail!("Submodule `{}` is not available locally and `--offline` prevents cloning it from `{}`", path, fetch_url)
+            }
+            Err(_) => {}
+        }
+
+        let mut checkout_builder = CheckoutBuilder::new();
+        checkout_builder.force();
+
+        let mut update_options = SubmoduleUpdateOptions::new();
+        update_options.checkout(checkout_builder);
+        update_options.allow_fetch(!options.offline || is_local_url(&fetch_url));
+
+        submodule.update(true, Some(&mut update_options)).with_context(|| format!("Failed to update submodule `{}`", path))?;
+
+        let submodule_repo = submodule.open().with_context(|| format!("Failed to open submodule `{}`", path))?;
+        update_submodules(&submodule_repo, options)?;
+    }
+
+    Ok(())
+}
+
+// Like `git submodule deinit`, the emptied submodule directories stay in place as the tree references them
+fn deinit_submodules(repo: &Repository) -> Result<()> {
+    let workdir = repo.workdir().context("Repository has no working directory")?;
+    let mut config = repo.config().context("Failed to open repository config")?;
+
+    for submodule in repo.submodules().context("Failed to list submodules")? {
+        if submodule.open().is_err() {
+            continue;
+        }
+
+        let path = workdir.join(submodule.path());
+        remove_dir_all(&path).with_context(|| format!("Failed to remove submodule `{}`", submodule.path().to_string_lossy()))?;
+        create_dir_all(&path).with_context(|| format!("Failed to remove submodule `{}`", submodule.path().to_string_lossy()))?;
+
+        if let Some(name) = submodule.name() {
+            let _ = config.remove(&format!("submodule.{}.url", name));
+        }
+    }
+
+    Ok(())
+}
+
+fn collect_submodules(repo: &Repository, prefix: &Path, submodules: &mut Vec<GitSubmodule>) -> Result<()> {
+    for submodule in repo.submodules().context("Failed to list submodules")? {
+        let path = prefix.join(submodule.path());
+        let submodule_repo = submodule.open().with_context(|| format!("Submodule `{}` is not initialized", path.to_string_lossy()))?;
+        let head = submodule_repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .with_context(|| format!("Failed to resolve HEAD of submodule `{}`", path.to_string_lossy()))?;
+
+        submodules.push(GitSubmodule {
+            path: path.to_string_lossy().to_string(),
+            commit: head.id().to_string(),
+        });
+        collect_submodules(&submodule_repo, &path, submodules)?;
+    }
+
+    Ok(())
+}
+
+pub fn submodule_state(repo_path: &Path) -> Result<Vec<GitSubmodule>> {
+    let repo = Repository::open(repo_path).context("Failed to open repository")?;
+
+    let mut submodules = Vec::new();
+    collect_submodules(&repo, Path::new(""), &mut submodules)?;
+    Ok(submodules)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::write, path::Path, rc::Rc};
+
+    use git2::{BranchType, Oid, Repository, Signature, build::CheckoutBuilder};
+
+    use super::{CheckoutState, GitOptions, clone, resolve_remote, state, update};
+    use crate::testing::TestDir;
+
+    fn commit(repo: &Repository, file: &str, content: &str) -> Oid {
+        write(repo.workdir().unwrap().join(file), content).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        index.write().unwrap();
+
+        let signature = Signature::now("Test", "test@localhost").unwrap();
+        let parent = repo.head().and_then(|head| head.peel_to_commit()).ok();
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, content, &tree, &parents).unwrap()
+    }
+
+    fn upstream(dir: &Path) -> (String, Oid) {
+        let repo = Repository::init(dir.join("upstream")).unwrap();
+        let first = commit(&repo, "a.txt", "first");
+        commit(&repo, "a.txt", "second");
+        (dir.join("upstream").to_string_lossy().to_string(), first)
+    }
+
+    fn is_clean(state: CheckoutState) -> bool {
+        matches!(state, CheckoutState::Clean)
+    }
+
+    #[test]
+    fn local_commits_are_detected() {
+        let dir = TestDir::new("git-local-commits");
+        let (url, _) = upstream(&dir);
+        let checkout_path = dir.join("checkout");
+        let checkout = clone(&url, "master", None, &checkout_path, &GitOptions::default()).unwrap();
+
+        assert!(is_clean(state(&checkout_path, Some(&checkout.head)).unwrap()));
+        assert!(is_clean(state(&checkout_path, None).unwrap()));
+
+        // A local branch with its own commit, while HEAD stays detached at the checked out commit
+        let repo = Repository::open(&checkout_path).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.branch("work", &head, false).unwrap();
+        repo.set_head("refs/heads/work").unwrap();
+        commit(&repo, "b.txt", "local");
+        repo.set_head_detached(head.id()).unwrap();
+        repo.checkout_head(Some(CheckoutBuilder::new().force().remove_untracked(true))).unwrap();
+        assert!(matches!(state(&checkout_path, Some(&checkout.head)).unwrap(), CheckoutState::LocalBranch(branch) if branch == "work"));
+
+        // A local commit on the detached HEAD without a recorded commit to compare against
+        repo.find_branch("work", BranchType::Local).unwrap().delete().unwrap();
+        let local = commit(&repo, "c.txt", "detached");
+        assert!(matches!(state(&checkout_path, None).unwrap(), CheckoutState::Moved(head) if head == local.to_string()));
+    }
+
+    #[test]
+    fn abbreviated_commit_hash() {
+        let dir = TestDir::new("git-abbreviated");
+        let (url, first) = upstream(&dir);
+        let short = &first.to_string()[..7];
+
+        let checkout = clone(&url, short, None, &dir.join("checkout"), &GitOptions::default()).unwrap();
+        assert_eq!(checkout.commit, first.to_string());
+        assert!(resolve_remote(&url, short).unwrap().is_none());
+        assert!(clone(&url, "ffffff0", None, &dir.join("missing"), &GitOptions::default()).is_err());
+    }
+
+    #[test]
+    fn disabled_submodules_are_deinitialized() {
+        let dir = TestDir::new("git-submodules");
+        let (sub_url, _) = upstream(&dir);
+
+        let repo = Repository::init(dir.join("main")).unwrap();
+        commit(&repo, "main.txt", "main");
+        let mut submodule = repo.submodule(&sub_url, Path::new("sub"), true).unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit(&repo, "main.txt", "with submodule");
+        let url = dir.join("main").to_string_lossy().to_string();
+
+        let checkout_path = dir.join("checkout");
+        let options = GitOptions {
+            submodules: true,
+            ..GitOptions::default()
+        };
+        let checkout = clone(&url, "master", None, &checkout_path, &options).unwrap();
+        assert!(checkout_path.join("sub/a.txt").exists());
+
+        let checkout = update(&url, "master", None, &checkout_path, Some(&checkout.head), false, &GitOptions::default()).unwrap();
+        assert!(checkout_path.join("sub").is_dir());
+        assert!(!checkout_path.join("sub/a.txt").exists());
+        assert!(is_clean(state(&checkout_path, Some(&checkout.head)).unwrap()));
+    }
+
+    #[test]
+    fn submodules_use_mirrors() {
+        let dir = TestDir::new("git-submodule-mirrors");
+        let (sub_url, _) = upstream(&dir);
+
+        let repo = Repository::init(dir.join("main")).unwrap();
+        commit(&repo, "main.txt", "main");
+        let mut submodule = repo.submodule(&sub_url, Path::new("sub"), true).unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit(&repo, ".gitmodules", "[submodule \"sub\"]\n\tpath = sub\n\turl = https://example.invalid/upstream\n");
+        let url = dir.join("main").to_string_lossy().to_string();
+
+        let mut options = GitOptions {
+            submodules: true,
+            offline: true,
+            ..GitOptions::default()
+        };
+        let Err(err) = clone(&url, "master", None, &dir.join("offline"), &options) else {
+            panic!("offline clone of a remote submodule should fail");
+        };
+        assert_eq!(
+            format!("{:#}", err),
+            "Submodule `sub` is not available locally and `--offline` prevents cloning it from `https://example.invalid/upstream`"
+        );
+
+        let mirrors = format!("[mirrors]\n\"https://example.invalid/\" = '{}/'\n", dir.to_string_lossy());
+        options.mirrors = Rc::new(toml::from_str(&mirrors).unwrap());
+        let checkout_path = dir.join("checkout");
+        let checkout = clone(&url, "master", None, &checkout_path, &options).unwrap();
+        assert!(checkout_path.join("sub/a.txt").exists());
+        assert!(is_clean(state(&checkout_path, Some(&checkout.head)).unwrap()));
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    fs::{exists, read_to_string, write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+pub const LOCK_FILE: &str = "fab.lock";
+
+const CURRENT_VERSION: i64 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct LockedGit {
+    pub name: String,
+    pub url: String,
+    pub revision: String,
+    pub commit: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Lockfile {
+    #[serde(skip_serializing, skip_deserializing)]
+    path: PathBuf,
+    #[serde(skip_serializing, skip_deserializing)]
+    changed: bool,
+
+    version: i64,
+
+    #[serde(default)]
+    pub git: Vec<LockedGit>,
+}
+
+impl Lockfile {
+    pub fn load(project_dir: &Path) -> Result<Lockfile> {
+        let path = project_dir.join(LOCK_FILE);
+
+        if !exists(&path)? {
+            return Ok(Lockfile {
+                path,
+                changed: false,
+                version: CURRENT_VERSION,
+                git: Vec::new(),
+            });
+        }
+
+        let lock_data = read_to_string(&path).context("Failed to read lockfile")?;
+        let mut lockfile: Lockfile = toml::from_str(&lock_data).context("Failed to parse lockfile")?;
+
+        if lockfile.version > CURRENT_VERSION {
+            bail!("Unsupported lockfile version: {}", lockfile.version);
+        }
+
+        lockfile.path = path;
+        Ok(lockfile)
+    }
+
+    pub fn find_git(&self, name: &str, url: &str, revision: &str) -> Option<&LockedGit> {
+        self.git.iter().find(|locked| locked.name == name && locked.url == url && locked.revision == revision)
+    }
+
+    pub fn lock_git(&mut self, locked: LockedGit) {
+        match self.git.iter_mut().find(|v| v.name == locked.name) {
+            Some(existing) => {
+                if *existing == locked {
+                    return;
+                }
+                *existing = locked;
+            }
+            None => {
+                self.git.push(locked);
+                self.git.sort_by(|a, b| a.name.cmp(&b.name));
+            }
+        }
+        self.changed = true;
+    }
+
+    pub fn retain_git(&mut self, names: &[&str]) {
+        let count = self.git.len();
+        self.git.retain(|locked| names.contains(&locked.name.as_str()));
+        if self.git.len() != count {
+            self.changed = true;
+        }
+    }
+
+    pub fn update(&self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+
+        let lock_data = toml::to_string(self).context("Failed to serialize lockfile")?;
+        write(&self.path, format!("# Generated by Fabricate, commit this file to pin dependencies\n{}", lock_data)).context("Failed to write lockfile")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{exists, read_to_string, remove_file, write};
+
+    use super::{LOCK_FILE, LockedGit, Lockfile};
+    use crate::testing::TestDir;
+
+    fn locked(name: &str, commit: &str) -> LockedGit {
+        LockedGit {
+            name: String::from(name),
+            url: format!("https://example.com/{}.git", name),
+            revision: String::from("main"),
+            commit: String::from(commit),
+        }
+    }
+
+    #[test]
+    fn lockfile_round_trip() {
+        let dir = TestDir::new("lockfile");
+
+        let mut lockfile = Lockfile::load(&dir).unwrap();
+        lockfile.lock_git(locked("zlib", "1111"));
+        lockfile.lock_git(locked("libpng", "2222"));
+        lockfile.update().unwrap();
+
+        let lockfile = Lockfile::load(&dir).unwrap();
+        let names: Vec<&str> = lockfile.git.iter().map(|locked| locked.name.as_str()).collect();
+        assert_eq!(names, ["libpng", "zlib"]);
+        assert!(lockfile.find_git("zlib", "https://example.com/zlib.git", "main").is_some_and(|locked| locked.commit == "1111"));
+        assert!(lockfile.find_git("zlib", "https://example.com/zlib.git", "v2").is_none());
+
+        // Locking identical entries does not rewrite the file
+        let mut lockfile = lockfile;
+        remove_file(dir.join(LOCK_FILE)).unwrap();
+        lockfile.lock_git(locked("zlib", "1111"));
+        lockfile.retain_git(&["libpng", "zlib"]);
+        lockfile.update().unwrap();
+        assert!(!exists(dir.join(LOCK_FILE)).unwrap());
+
+        lockfile.retain_git(&["zlib"]);
+        lockfile.update().unwrap();
+        let lockfile = Lockfile::load(&dir).unwrap();
+        assert_eq!(lockfile.git.len(), 1);
+        assert!(read_to_string(dir.join(LOCK_FILE)).unwrap().starts_with("# Generated by Fabricate"));
+    }
+
+    #[test]
+    fn lockfile_rejects_newer_versions() {
+        let dir = TestDir::new("lockfile-version");
+        write(dir.join(LOCK_FILE), "version = 2\n").unwrap();
+        assert!(Lockfile::load(&dir).is_err());
+    }
+}
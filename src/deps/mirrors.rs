@@ -0,0 +1,51 @@
+use std::{
+    collections::HashMap,
+    env::var_os,
+    fs::{exists, read_to_string},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+const MIRRORS_FILE: &str = "mirrors.toml";
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mirrors {
+    #[serde(default)]
+    mirrors: HashMap<String, String>,
+}
+
+fn config_dir() -> Option<PathBuf> {
+    if let Some(config_home) = var_os("XDG_CONFIG_HOME").filter(|value| !value.is_empty()) {
+        return Some(PathBuf::from(config_home).join("fabricate"));
+    }
+
+    var_os("HOME").filter(|value| !value.is_empty()).map(|home| PathBuf::from(home).join(".config").join("fabricate"))
+}
+
+impl Mirrors {
+    pub fn load() -> Result<Mirrors> {
+        let path = match config_dir() {
+            None => return Ok(Mirrors::default()),
+            Some(config_dir) => config_dir.join(MIRRORS_FILE),
+        };
+
+        if !exists(&path)? {
+            return Ok(Mirrors::default());
+        }
+
+        let mirrors_data = read_to_string(&path).with_context(|| format!("Failed to read `{}`", path.to_string_lossy()))?;
+        toml::from_str(&mirrors_data).with_context(|| format!("Failed to parse `{}`", path.to_string_lossy()))
+    }
+
+    pub fn rewrite(&self, url: &str) -> String {
+        let mirror = self.mirrors.iter().filter(|(prefix, _)| url.starts_with(prefix.as_str())).max_by_key(|(prefix, _)| prefix.len());
+
+        match mirror {
+            None => url.to_string(),
+            Some((prefix, replacement)) => format!("{}{}", replacement, &url[prefix.len()..]),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    collections::HashMap,
+    fs::{File, exists, read_dir, remove_dir_all, remove_file},
+    io::copy,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    cache::FabricateCache,
+    deps::{
+        git::CheckoutState,
+        lock::{LockedGit, Lockfile},
+        mirrors::Mirrors,
+    },
+};
+
+pub mod archive;
+pub mod git;
+pub mod lock;
+pub mod mirrors;
+pub mod vendor;
+
+pub struct DependencyOptions {
+    pub overrides: HashMap<String, String>,
+    pub force: bool,
+    pub offline: bool,
+    pub mirrors: Mirrors,
+    pub vendor_dir: Option<PathBuf>,
+}
+
+pub fn is_local_url(url: &str) -> bool {
+    url.starts_with("file://") || !url.contains(':')
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).with_context(|| format!("Failed to open `{}`", path.to_string_lossy()))?;
+    let mut hasher = Sha256::new();
+    copy(&mut file, &mut hasher).with_context(|| format!("Failed to hash `{}`", path.to_string_lossy()))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn short_commit(commit: &str) -> &str {
+    &commit[..commit.len().min(12)]
+}
+
+pub fn update(build_dir: &Path, names: Vec<String>) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+    let mut lockfile = Lockfile::load(&cache.project_dir)?;
+    let mirrors = Mirrors::load()?;
+
+    for name in names.iter() {
+        if !lockfile.git.iter().any(|locked| locked.name == *name) {
+            bail!("Git dependency `{}` is not locked", name);
+        }
+    }
+
+    let mut updated = false;
+    for locked in lockfile.git.clone() {
+        if !names.is_empty() && !names.contains(&locked.name) {
+            continue;
+        }
+
+        let commit = match git::resolve_remote(&mirrors.rewrite(&locked.url), &locked.revision).with_context(|| format!("Failed to update git dependency `{}`", locked.name))? {
+            None => {
+                println!("Git dependency `{}` is pinned to a commit ({})", locked.name, short_commit(&locked.commit));
+                continue;
+            }
+            Some(commit) => commit,
+        };
+
+        if commit == locked.commit {
+            println!("Git dependency `{}` is up to date ({})", locked.name, short_commit(&commit));
+            continue;
+        }
+
+        println!("Git dependency `{}` updated ({} -> {})", locked.name, short_commit(&locked.commit), short_commit(&commit));
+        lockfile.lock_git(LockedGit { commit, ..locked });
+        updated = true;
+    }
+
+    lockfile.update()?;
+
+    if updated {
+        println!("Run `fabricate setup` to check out the updated dependencies");
+    }
+
+    Ok(())
+}
+
+pub fn list(build_dir: &Path) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    for dep in cache.git_dependencies.iter() {
+        println!("{} (git)", dep.name);
+        println!("    url:      {}", dep.url);
+        println!("    revision: {}", dep.revision);
+        if let Some(commit) = &dep.commit {
+            println!("    commit:   {}", commit);
+        }
+        if let Some(override_path) = &dep.override_path {
+            println!("    override: {}", override_path.to_string_lossy());
+        }
+        if let Some(vendor_path) = &dep.vendor_path {
+            println!("    vendored: {}", vendor_path.to_string_lossy());
+        }
+    }
+
+    for dep in cache.archive_dependencies.iter() {
+        println!("{} (archive)", dep.name);
+        println!("    url:      {}", dep.url);
+        println!("    sha256:   {}", dep.sha256);
+        if let Some(override_path) = &dep.override_path {
+            println!("    override: {}", override_path.to_string_lossy());
+        }
+        if let Some(vendor_path) = &dep.vendor_path {
+            println!("    vendored: {}", vendor_path.to_string_lossy());
+        }
+    }
+
+    Ok(())
+}
+
+pub fn status(build_dir: &Path) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    for dep in cache.git_dependencies.iter() {
+        let repo_path = build_dir.join("git").join(&dep.name);
+
+        let state = if let Some(override_path) = &dep.override_path {
+            format!("overridden by `{}`", override_path.to_string_lossy())
+        } else if let Some(vendor_path) = &dep.vendor_path {
+            format!("vendored in `{}`", vendor_path.to_string_lossy())
+        } else if !exists(&repo_path)? {
+            String::from("missing, run `fabricate setup`")
+        } else {
+            let expected_commit = dep.head.as_deref().or(dep.commit.as_deref());
+            match git::state(&repo_path, expected_commit).with_context(|| format!("Failed to read status of git dependency `{}`", dep.name))? {
+                CheckoutState::Clean => format!("clean at {}", short_commit(expected_commit.unwrap_or_default())),
+                CheckoutState::Modified(changes) => match changes.len() {
+                    1 => String::from("modified (1 changed file)"),
+                    count => format!("modified ({} changed files)", count),
+                },
+                CheckoutState::Moved(head) => match expected_commit {
+                    Some(expected_commit) => format!("at {} instead of {}, may contain local commits", short_commit(&head), short_commit(expected_commit)),
+                    None => format!("at {}, may contain local commits", short_commit(&head)),
+                },
+                CheckoutState::LocalBranch(branch) => format!("local branch `{}` has commits that are not on the remote", branch),
+            }
+        };
+
+        println!("{} (git): {}", dep.name, state);
+    }
+
+    for dep in cache.archive_dependencies.iter() {
+        let state = if let Some(override_path) = &dep.override_path {
+            format!("overridden by `{}`", override_path.to_string_lossy())
+        } else if let Some(vendor_path) = &dep.vendor_path {
+            format!("vendored in `{}`", vendor_path.to_string_lossy())
+        } else if !exists(build_dir.join("archives").join(&dep.name))? {
+            String::from("missing, run `fabricate setup`")
+        } else {
+            String::from("extracted")
+        };
+
+        println!("{} (archive): {}", dep.name, state);
+    }
+
+    Ok(())
+}
+
+pub fn clean(build_dir: &Path, force: bool) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    let git_names: Vec<&str> = cache.git_dependencies.iter().map(|dep| dep.name.as_str()).collect();
+    let archive_names: Vec<&str> = cache.archive_dependencies.iter().map(|dep| dep.name.as_str()).collect();
+
+    for (dir, names) in [("git", git_names), ("archives", archive_names)] {
+        let deps_dir = build_dir.join(dir);
+        if !exists(&deps_dir)? {
+            continue;
+        }
+
+        for entry in read_dir(&deps_dir).with_context(|| format!("Failed to read `{}`", deps_dir.to_string_lossy()))? {
+            let entry = entry.context("Failed to read directory entry")?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if names.contains(&name.as_str()) {
+                continue;
+            }
+
+            let path = entry.path();
+            if dir == "git" && !force {
+                let reason = match git::state(&path, None) {
+                    Ok(CheckoutState::Modified(_)) => Some(String::from("it has uncommitted changes")),
+                    Ok(CheckoutState::Moved(head)) => Some(format!("it is at {} which may contain local commits", short_commit(&head))),
+                    Ok(CheckoutState::LocalBranch(branch)) => Some(format!("local branch `{}` has commits that are not on the remote", branch)),
+                    Ok(CheckoutState::Clean) | Err(_) => None,
+                };
+
+                if let Some(reason) = reason {
+                    println!("Skipping `{}/{}`, {} (pass `--force` to remove it anyway)", dir, name, reason);
+                    continue;
+                }
+            }
+
+            if path.is_dir() {
+                remove_dir_all(&path).with_context(|| format!("Failed to remove `{}`", path.to_string_lossy()))?;
+            } else {
+                remove_file(&path).with_context(|| format!("Failed to remove `{}`", path.to_string_lossy()))?;
+            }
+            println!("Removed `{}/{}`", dir, name);
+        }
+    }
+
+    Ok(())
+}
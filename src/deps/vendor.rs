@@ -0,0 +1,211 @@
+use std::{
+    fs::{copy, create_dir_all, exists, read_dir, read_link, read_to_string, remove_dir_all, write},
+    path::{Path, PathBuf, absolute},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cache::FabricateCache,
+    deps::git::{self, CheckoutState},
+};
+
+pub const VENDOR_DIR: &str = "vendor";
+pub const VENDOR_MANIFEST: &str = "fab-vendor.toml";
+
+const CURRENT_VERSION: i64 = 1;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct VendoredGit {
+    pub name: String,
+    pub url: String,
+    pub revision: String,
+    pub commit: String,
+    #[serde(default)]
+    pub submodules: bool,
+    #[serde(default)]
+    pub patches: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct VendoredArchive {
+    pub name: String,
+    pub url: String,
+    pub sha256: String,
+    #[serde(default)]
+    pub strip_components: u32,
+    #[serde(default)]
+    pub patches: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VendorManifest {
+    #[serde(skip_serializing, skip_deserializing)]
+    pub dir: PathBuf,
+
+    version: i64,
+
+    #[serde(default)]
+    pub git: Vec<VendoredGit>,
+    #[serde(default)]
+    pub archives: Vec<VendoredArchive>,
+}
+
+impl VendorManifest {
+    pub fn load(dir: &Path) -> Result<Option<VendorManifest>> {
+        let path = dir.join(VENDOR_MANIFEST);
+        if !exists(&path)? {
+            return Ok(None);
+        }
+
+        let manifest_data = read_to_string(&path).context("Failed to read vendor manifest")?;
+        let mut manifest: VendorManifest = toml::from_str(&manifest_data).context("Failed to parse vendor manifest")?;
+
+        if manifest.version > CURRENT_VERSION {
+            bail!("Unsupported vendor manifest version: {}", manifest.version);
+        }
+
+        manifest.dir = dir.to_path_buf();
+        Ok(Some(manifest))
+    }
+
+    pub fn git_path(&self, name: &str) -> PathBuf {
+        self.dir.join("git").join(name)
+    }
+
+    pub fn archive_path(&self, name: &str) -> PathBuf {
+        self.dir.join("archives").join(name)
+    }
+}
+
+fn copy_tree(src: &Path, dest: &Path) -> Result<()> {
+    create_dir_all(dest).with_context(|| format!("Failed to create `{}`", dest.to_string_lossy()))?;
+
+    for entry in read_dir(src).with_context(|| format!("Failed to read `{}`", src.to_string_lossy()))? {
+        let entry = entry.context("Failed to read directory entry")?;
+        if entry.file_name() == ".git" {
+            continue;
+        }
+
+        let src_path = entry.path();
+        let dest_path = dest.join(entry.file_name());
+        let file_type = entry.file_type().context("Failed to read file type")?;
+
+        if file_type.is_symlink() {
+            let target = read_link(&src_path).with_context(|| format!("Failed to read link `{}`", src_path.to_string_lossy()))?;
+
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &dest_path).with_context(|| format!("Failed to create link `{}`", dest_path.to_string_lossy()))?;
+
+            #[cfg(not(unix))]
+            bail!("Failed to copy link `{}`, symbolic links are only supported on unix", src_path.to_string_lossy());
+        } else if file_type.is_dir() {
+            copy_tree(&src_path, &dest_path)?;
+        } else {
+            copy(&src_path, &dest_path).with_context(|| format!("Failed to copy `{}`", src_path.to_string_lossy()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn replace_tree(src: &Path, dest: &Path) -> Result<()> {
+    if exists(dest)? {
+        remove_dir_all(dest).with_context(|| format!("Failed to remove `{}`", dest.to_string_lossy()))?;
+    }
+    copy_tree(src, dest)
+}
+
+pub fn vendor(build_dir: &Path, dir: Option<PathBuf>) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    let dir = match dir {
+        None => cache.project_dir.join(VENDOR_DIR),
+        Some(dir) => absolute(dir).context("Failed to resolve vendor directory path")?,
+    };
+
+    let previous = VendorManifest::load(&dir)?;
+    let mut manifest = VendorManifest {
+        dir: dir.clone(),
+        version: CURRENT_VERSION,
+        git: Vec::new(),
+        archives: Vec::new(),
+    };
+
+    for dep in cache.git_dependencies.iter() {
+        let commit = match &dep.commit {
+            None => bail!("Git dependency `{}` is overridden, run `fabricate setup` without overriding it before vendoring", dep.name),
+            Some(commit) => commit.clone(),
+        };
+
+        let dest = manifest.git_path(&dep.name);
+        match &dep.vendor_path {
+            Some(vendor_path) if *vendor_path == dest => {}
+            Some(vendor_path) => replace_tree(vendor_path, &dest).with_context(|| format!("Failed to vendor git dependency `{}`", dep.name))?,
+            None => {
+                let repo_path = build_dir.join("git").join(&dep.name);
+                let expected_commit = dep.head.as_deref().unwrap_or(&commit);
+                match git::state(&repo_path, Some(expected_commit)).with_context(|| format!("Failed to read status of git dependency `{}`", dep.name))? {
+                    CheckoutState::Clean => {}
+                    _ => bail!("Git dependency `{}` has local changes, see `fabricate deps status`", dep.name),
+                }
+
+                replace_tree(&repo_path, &dest).with_context(|| format!("Failed to vendor git dependency `{}`", dep.name))?;
+            }
+        }
+
+        println!("Vendored git dependency `{}` at {}", dep.name, commit);
+        manifest.git.push(VendoredGit {
+            name: dep.name.clone(),
+            url: dep.url.clone(),
+            revision: dep.revision.clone(),
+            commit,
+            submodules: dep.submodules.is_some(),
+            patches: dep.patches.clone(),
+        });
+    }
+
+    for dep in cache.archive_dependencies.iter() {
+        if dep.override_path.is_some() {
+            bail!("Archive dependency `{}` is overridden, run `fabricate setup` without overriding it before vendoring", dep.name);
+        }
+
+        let dest = manifest.archive_path(&dep.name);
+        let src = dep.vendor_path.clone().unwrap_or(build_dir.join("archives").join(&dep.name));
+        if src != dest {
+            replace_tree(&src, &dest).with_context(|| format!("Failed to vendor archive dependency `{}`", dep.name))?;
+        }
+
+        println!("Vendored archive dependency `{}`", dep.name);
+        manifest.archives.push(VendoredArchive {
+            name: dep.name.clone(),
+            url: dep.url.clone(),
+            sha256: dep.sha256.clone(),
+            strip_components: dep.strip_components,
+            patches: dep.patches.clone(),
+        });
+    }
+
+    if let Some(previous) = previous {
+        for stale in previous.git.iter().filter(|stale| !manifest.git.iter().any(|vendored| vendored.name == stale.name)) {
+            let path = manifest.git_path(&stale.name);
+            if exists(&path)? {
+                remove_dir_all(&path).with_context(|| format!("Failed to remove `{}`", path.to_string_lossy()))?;
+            }
+        }
+
+        for stale in previous.archives.iter().filter(|stale| !manifest.archives.iter().any(|vendored| vendored.name == stale.name)) {
+            let path = manifest.archive_path(&stale.name);
+            if exists(&path)? {
+                remove_dir_all(&path).with_context(|| format!("Failed to remove `{}`", path.to_string_lossy()))?;
+            }
+        }
+    }
+
+    create_dir_all(&dir).context("Failed to create vendor directory")?;
+    let manifest_data = toml::to_string(&manifest).context("Failed to serialize vendor manifest")?;
+    write(dir.join(VENDOR_MANIFEST), format!("# Generated by Fabricate, use `fabricate vendor` to update\n{}", manifest_data)).context("Failed to write vendor manifest")?;
+
+    Ok(())
+}
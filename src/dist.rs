@@ -0,0 +1,256 @@
+use std::{
+    env::current_exe,
+    fs::{File, create_dir_all, exists, read, remove_dir_all, write},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use flate2::{Compression, GzBuilder, read::GzDecoder};
+use git2::{ObjectType, Oid, Repository, StatusOptions, TreeWalkMode, TreeWalkResult};
+use tar::Archive;
+
+use crate::{
+    cache::FabricateCache,
+    deps::{
+        lock::LOCK_FILE,
+        vendor::{VENDOR_DIR, VendorManifest},
+    },
+    package::{PackageEntry, PackageEntryKind, collect_entries, write_tar},
+    reproducible::source_date_epoch,
+};
+
+const STAGING_DIR: &str = "dist-staging";
+const VERIFY_DIR: &str = "dist-verify";
+
+pub struct DistOptions {
+    pub include_vendor: bool,
+    pub include_lock: bool,
+    pub verify: bool,
+}
+
+// Blobs are written to the staging directory one at a time so that large trees are not held in memory
+fn tree_entries(repo: &Repository, project_dir: &Path, staging_dir: &Path) -> Result<(Vec<PackageEntry>, Oid, i64)> {
+    let workdir = match repo.workdir() {
+        None => bail!("The git repository of the project has no working directory"),
+        Some(workdir) => workdir.canonicalize().context("Failed to resolve repository path")?,
+    };
+    let project_path = project_dir.strip_prefix(&workdir).context("The project is not inside its git repository")?;
+
+    let commit = repo.head().and_then(|head| head.peel_to_commit()).context("Failed to resolve HEAD of the project repository")?;
+    let mut tree = commit.tree().context("Failed to read the tree of HEAD")?;
+    if !project_path.as_os_str().is_empty() {
+        tree = tree
+            .get_path(project_path)
+            .and_then(|entry| entry.to_object(repo))
+            .and_then(|object| object.peel_to_tree())
+            .with_context(|| format!("Failed to find `{}` in HEAD", project_path.to_string_lossy()))?;
+    }
+
+    let mut items = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        items.push((format!("{}{}", dir, String::from_utf8_lossy(entry.name_bytes())), entry.id(), entry.filemode(), entry.kind()));
+        TreeWalkResult::Ok
+    })
+    .context("Failed to walk the tree of HEAD")?;
+
+    let mut entries = Vec::new();
+    for (path, id, filemode, kind) in items {
+        match kind {
+            Some(ObjectType::Tree) => entries.push(PackageEntry {
+                path,
+                mode: 0o755,
+                size: 0,
+                kind: PackageEntryKind::Directory,
+            }),
+            Some(ObjectType::Blob) => {
+                let blob = repo.find_blob(id).with_context(|| format!("Failed to read `{}` from HEAD", path))?;
+                let content = blob.content();
+                if filemode == 0o120000 {
+                    entries.push(PackageEntry {
+                        path,
+                        mode: 0o777,
+                        size: content.len() as u64,
+                        kind: PackageEntryKind::Symlink(PathBuf::from(String::from_utf8_lossy(content).to_string())),
+                    });
+                    continue;
+                }
+
+                let staged = staging_dir.join(&path);
+                if let Some(parent) = staged.parent() {
+                    create_dir_all(parent).context("Failed to create staging directory")?;
+                }
+                write(&staged, content).with_context(|| format!("Failed to stage `{}`", path))?;
+                entries.push(PackageEntry {
+                    path,
+                    mode: if filemode == 0o100755 { 0o755 } else { 0o644 },
+                    size: content.len() as u64,
+                    kind: PackageEntryKind::File(staged),
+                });
+            }
+            _ => println!("Warning: skipping submodule `{}`, submodules are not included in the source archive", path),
+        }
+    }
+
+    let mut status_options = StatusOptions::new();
+    status_options.include_untracked(true).include_ignored(false);
+    let statuses = repo.statuses(Some(&mut status_options)).context("Failed to read repository status")?;
+    let changes = statuses.iter().filter(|entry| entry.path().is_some_and(|path| Path::new(path).starts_with(project_path))).count();
+    if changes > 0 {
+        println!("Warning: {} uncommitted or untracked files are not included in the source archive", changes);
+    }
+
+    Ok((entries, commit.id(), commit.time().seconds()))
+}
+
+fn verify(build_dir: &Path, archive: &Path, name: &str, cache: &FabricateCache, offline: bool) -> Result<()> {
+    let verify_dir = build_dir.join(VERIFY_DIR);
+    if exists(&verify_dir)? {
+        remove_dir_all(&verify_dir).context("Failed to remove previous verification directory")?;
+    }
+    create_dir_all(&verify_dir).context("Failed to create verification directory")?;
+
+    let file = File::open(archive).context("Failed to open source archive")?;
+    Archive::new(GzDecoder::new(file)).unpack(&verify_dir).context("Failed to unpack source archive")?;
+
+    let project_dir = verify_dir.join(name);
+    let fabricate = current_exe().context("Failed to resolve the fabricate executable path")?;
+
+    // The config and a cross file from the project are taken from the unpacked tree instead of the original one
+    let config_file = project_dir.join(cache.config_file.as_deref().unwrap_or(Path::new("fab.lua")));
+    let cross_file = cache.cross_file.as_ref().map(|cross_file| match cross_file.strip_prefix(&cache.project_dir) {
+        Ok(relative) => project_dir.join(relative),
+        Err(_) => cross_file.clone(),
+    });
+
+    let mut setup_cmd = Command::new(&fabricate);
+    setup_cmd
+        .arg("--build-dir")
+        .arg(project_dir.join("build"))
+        .arg("setup")
+        .arg("--config")
+        .arg(&config_file)
+        .arg("--prefix")
+        .arg(&cache.prefix);
+    for (dir, path) in cache.install_dirs.iter().filter(|(dir, _)| *dir != "prefix") {
+        setup_cmd.arg(format!("--{}", dir)).arg(path);
+    }
+    for (key, value) in cache.options.iter() {
+        setup_cmd.arg("--option").arg(format!("{}={}", key, value));
+    }
+    if let Some(cross_file) = &cross_file {
+        setup_cmd.arg("--cross-file").arg(cross_file);
+    }
+    if offline {
+        setup_cmd.arg("--offline");
+    }
+
+    let mut build_cmd = Command::new(&fabricate);
+    build_cmd.arg("--build-dir").arg(project_dir.join("build")).arg("build");
+
+    for (step, mut cmd) in [("setup", setup_cmd), ("build", build_cmd)] {
+        println!("Verifying source archive: {}", step);
+        let status = cmd.status().with_context(|| format!("Failed to run {} of the unpacked source archive", step))?;
+        if !status.success() {
+            bail!(
+                "Verification of the source archive failed during {}, the unpacked tree is kept in `{}`",
+                step,
+                verify_dir.to_string_lossy()
+            );
+        }
+    }
+
+    remove_dir_all(&verify_dir).context("Failed to remove verification directory")?;
+    Ok(())
+}
+
+pub fn dist(build_dir: &Path, options: DistOptions) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    let staging_dir = build_dir.join(STAGING_DIR);
+    if exists(&staging_dir)? {
+        remove_dir_all(&staging_dir).context("Failed to remove previous staging directory")?;
+    }
+    create_dir_all(&staging_dir).context("Failed to create staging directory")?;
+
+    let repo = Repository::discover(&cache.project_dir).context("Failed to open the git repository of the project")?;
+    let (mut entries, commit, commit_time) = tree_entries(&repo, &cache.project_dir, &staging_dir)?;
+
+    if options.include_lock {
+        let lock_path = cache.project_dir.join(LOCK_FILE);
+        if !exists(&lock_path)? {
+            bail!("Cannot include `{}`, it does not exist", LOCK_FILE);
+        }
+
+        let content = read(&lock_path).context("Failed to read lockfile")?;
+        entries.retain(|entry| entry.path != LOCK_FILE);
+        entries.push(PackageEntry {
+            path: String::from(LOCK_FILE),
+            mode: 0o644,
+            size: content.len() as u64,
+            kind: PackageEntryKind::Data(content),
+        });
+    }
+
+    if options.include_vendor {
+        let vendor_dir = cache.project_dir.join(VENDOR_DIR);
+        if VendorManifest::load(&vendor_dir)?.is_none() {
+            bail!("Cannot include vendored dependencies, `{}` does not exist, run `fabricate vendor` first", vendor_dir.to_string_lossy());
+        }
+
+        let vendor_prefix = format!("{}/", VENDOR_DIR);
+        entries.retain(|entry| entry.path != VENDOR_DIR && !entry.path.starts_with(&vendor_prefix));
+        entries.push(PackageEntry {
+            path: String::from(VENDOR_DIR),
+            mode: 0o755,
+            size: 0,
+            kind: PackageEntryKind::Directory,
+        });
+        for mut entry in collect_entries(&vendor_dir)? {
+            entry.path = format!("{}{}", vendor_prefix, entry.path);
+            entries.push(entry);
+        }
+    }
+
+    let name = match cache.project_dir.file_name() {
+        None => String::from("project"),
+        Some(name) => name.to_string_lossy().to_string(),
+    };
+
+    // All entries are placed in a top-level directory named after the project
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in entries.iter_mut() {
+        entry.path = format!("{}/{}", name, entry.path);
+    }
+    entries.insert(
+        0,
+        PackageEntry {
+            path: name.clone(),
+            mode: 0o755,
+            size: 0,
+            kind: PackageEntryKind::Directory,
+        },
+    );
+
+    let mtime = source_date_epoch()?.unwrap_or(commit_time.max(0) as u64);
+
+    let output = build_dir.join(format!("{}-src.tar.gz", name));
+    let file = File::create(&output).with_context(|| format!("Failed to create `{}`", output.to_string_lossy()))?;
+    let mut writer = BufWriter::new(file);
+    let encoder = GzBuilder::new().mtime(0).write(&mut writer, Compression::best());
+    write_tar(encoder, &entries, mtime)?.finish().context("Failed to finish gzip stream")?;
+    writer.flush().with_context(|| format!("Failed to write `{}`", output.to_string_lossy()))?;
+
+    remove_dir_all(&staging_dir).context("Failed to remove staging directory")?;
+
+    println!("Created source archive `{}` from commit {}", output.to_string_lossy(), commit);
+
+    if options.verify {
+        // A vendored archive has to build without fetching any dependency
+        verify(build_dir, &output, &name, &cache, options.include_vendor)?;
+        println!("Verified source archive `{}`", output.to_string_lossy());
+    }
+
+    Ok(())
+}
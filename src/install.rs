@@ -0,0 +1,394 @@
+use std::{
+    collections::HashMap,
+    fs::{copy, create_dir_all, exists, metadata, read_dir, read_link, read_to_string, remove_dir, remove_file, symlink_metadata, write},
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    cache::{FabricateCache, InstallEntry},
+    deps::sha256_file,
+};
+
+pub const INSTALL_MANIFEST: &str = "install_manifest.toml";
+
+/// Standard GNU installation directories with the directory their default is relative to and the default.
+pub const INSTALL_DIRS: &[(&str, &str, &str)] = &[
+    ("bindir", "prefix", "bin"),
+    ("sbindir", "prefix", "sbin"),
+    ("libdir", "prefix", "lib"),
+    ("libexecdir", "prefix", "libexec"),
+    ("includedir", "prefix", "include"),
+    ("sysconfdir", "prefix", "etc"),
+    ("localstatedir", "prefix", "var"),
+    ("datarootdir", "prefix", "share"),
+    ("datadir", "datarootdir", ""),
+    ("mandir", "datarootdir", "man"),
+    ("infodir", "datarootdir", "info"),
+    ("docdir", "datarootdir", "doc"),
+    ("localedir", "datarootdir", "locale"),
+];
+
+const CURRENT_VERSION: i64 = 1;
+
+pub struct InstallOptions {
+    pub prefix: String,
+    pub dirs: Vec<(String, String)>,
+    pub targets: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstalledFile {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub symlink: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InstallManifest {
+    #[serde(skip_serializing, skip_deserializing)]
+    path: PathBuf,
+
+    version: i64,
+
+    pub prefix: String,
+    #[serde(default)]
+    pub files: Vec<InstalledFile>,
+}
+
+impl InstallManifest {
+    fn path(build_dir: &Path, component: Option<&str>) -> PathBuf {
+        match component {
+            None => build_dir.join(INSTALL_MANIFEST),
+            Some(component) => build_dir.join(format!("install_manifest-{}.toml", component)),
+        }
+    }
+
+    fn load(path: PathBuf) -> Result<Option<InstallManifest>> {
+        if !exists(&path)? {
+            return Ok(None);
+        }
+
+        let manifest_data = read_to_string(&path).context("Failed to read install manifest")?;
+        let mut manifest: InstallManifest = toml::from_str(&manifest_data).context("Failed to parse install manifest")?;
+
+        if manifest.version > CURRENT_VERSION {
+            bail!("Unsupported install manifest version: {}", manifest.version);
+        }
+
+        manifest.path = path;
+        Ok(Some(manifest))
+    }
+
+    fn update(&self) -> Result<()> {
+        if self.files.is_empty() {
+            if exists(&self.path)? {
+                remove_file(&self.path).context("Failed to remove install manifest")?;
+            }
+            return Ok(());
+        }
+
+        write(&self.path, toml::to_string(self).context("Failed to serialize install manifest")?).context("Failed to write install manifest")?;
+        Ok(())
+    }
+}
+
+pub fn resolve_install_dirs(prefix: &str, overrides: Vec<(String, String)>) -> Result<HashMap<String, PathBuf>> {
+    let overrides: HashMap<String, String> = HashMap::from_iter(overrides);
+    for name in overrides.keys() {
+        if !INSTALL_DIRS.iter().any(|(dir, _, _)| dir == name) {
+            bail!("Unknown installation directory `{}`", name);
+        }
+    }
+
+    let mut dirs = HashMap::from([(String::from("prefix"), PathBuf::from(prefix))]);
+    for (name, base, default) in INSTALL_DIRS {
+        let base = dirs[*base].clone();
+        let dir = match overrides.get(*name) {
+            None if default.is_empty() => base,
+            None => base.join(default),
+            Some(value) => PathBuf::from(prefix).join(value),
+        };
+        dirs.insert(name.to_string(), dir);
+    }
+    Ok(dirs)
+}
+
+fn destination(dest_dir: Option<&str>, path: &Path) -> PathBuf {
+    let dest_dir = match dest_dir {
+        None => return path.to_path_buf(),
+        Some(dest_dir) => dest_dir,
+    };
+
+    let mut joined = PathBuf::from(dest_dir);
+    for c in path.components() {
+        match c {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => joined.push(".."),
+            Component::Normal(part) => joined.push(part),
+        }
+    }
+    joined
+}
+
+fn prune_empty_dirs(path: &Path, root: &Path) -> Result<()> {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if !current.starts_with(root) || current == root {
+            break;
+        }
+
+        if read_dir(current).with_context(|| format!("Failed to read `{}`", current.to_string_lossy()))?.next().is_some() {
+            break;
+        }
+
+        remove_dir(current).with_context(|| format!("Failed to remove `{}`", current.to_string_lossy()))?;
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+fn remove_existing(path: &Path) -> Result<()> {
+    if let Ok(metadata) = symlink_metadata(path) {
+        if metadata.is_dir() {
+            bail!("Destination `{}` is a directory", path.to_string_lossy());
+        }
+        remove_file(path).with_context(|| format!("Failed to replace `{}`", path.to_string_lossy()))?;
+    }
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    let parent = match path.parent() {
+        None => bail!("Could not resolve directory path of destination"),
+        Some(parent) => parent,
+    };
+
+    create_dir_all(parent).with_context(|| format!("Could not create dest dir `{}`", parent.to_string_lossy()))
+}
+
+fn is_up_to_date(src: &Path, dest: &Path, sha256: &str, mode: Option<u32>) -> Result<bool> {
+    let dest_metadata = match symlink_metadata(dest) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Ok(false),
+    };
+
+    if mode.is_none() && metadata(src).context("Failed to read source metadata")?.permissions() != dest_metadata.permissions() {
+        return Ok(false);
+    }
+
+    Ok(sha256_file(dest)? == sha256)
+}
+
+fn install_file(src: &Path, dest: &Path, mode: Option<u32>) -> Result<String> {
+    let sha256 = sha256_file(src)?;
+
+    // Only copy files whose content changed to keep the timestamps of unchanged files
+    if !is_up_to_date(src, dest, &sha256, mode)? {
+        create_parent(dest)?;
+        remove_existing(dest)?;
+        copy(src, dest).with_context(|| format!("Could not copy to `{}`", dest.to_string_lossy()))?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::{fs::set_permissions, os::unix::fs::PermissionsExt};
+        set_permissions(dest, PermissionsExt::from_mode(mode)).with_context(|| format!("Could not set the mode of `{}`", dest.to_string_lossy()))?;
+    }
+
+    #[cfg(not(unix))]
+    if mode.is_some() {
+        bail!("File modes are only supported on unix");
+    }
+
+    Ok(sha256)
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, dest: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, dest)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symlinks are only supported on unix"))
+}
+
+fn install_symlink(target: &Path, dest: &Path) -> Result<()> {
+    create_parent(dest)?;
+    remove_existing(dest)?;
+    create_symlink(target, dest).with_context(|| format!("Could not create symlink `{}`", dest.to_string_lossy()))
+}
+
+fn install_tree(src: &Path, path: &Path, dest_dir: Option<&str>, mode: Option<u32>, files: &mut Vec<InstalledFile>) -> Result<()> {
+    for entry in WalkDir::new(src).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("Failed to read `{}`", src.to_string_lossy()))?;
+        let relative = entry.path().strip_prefix(src).context("Failed to resolve path in directory")?;
+        let entry_path = path.join(relative);
+        let abs_dest = destination(dest_dir, &entry_path);
+
+        if entry.file_type().is_dir() {
+            create_dir_all(&abs_dest).with_context(|| format!("Could not create dest dir `{}`", abs_dest.to_string_lossy()))?;
+        } else if entry.file_type().is_symlink() {
+            let target = read_link(entry.path()).with_context(|| format!("Failed to read link `{}`", entry.path().to_string_lossy()))?;
+            install_symlink(&target, &abs_dest)?;
+            files.push(InstalledFile {
+                path: entry_path,
+                sha256: None,
+                symlink: Some(target),
+            });
+        } else {
+            files.push(InstalledFile {
+                sha256: Some(install_file(entry.path(), &abs_dest, mode)?),
+                path: entry_path,
+                symlink: None,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn components(cache: &FabricateCache) -> Vec<&str> {
+    let mut components: Vec<&str> = cache.installs.values().map(InstallEntry::component).collect();
+    components.sort();
+    components.dedup();
+    components
+}
+
+pub fn list_components(build_dir: &Path) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    for component in components(&cache) {
+        let count = cache.installs.values().filter(|entry| entry.component() == component).count();
+        match count {
+            1 => println!("{} (1 entry)", component),
+            count => println!("{} ({} entries)", component, count),
+        }
+    }
+
+    Ok(())
+}
+
+/// Installs the entries of the given component (or all entries) and returns the installed files.
+pub fn stage(build_dir: &Path, cache: &FabricateCache, dest_dir: Option<&str>, component: Option<&str>, files: &mut Vec<InstalledFile>) -> Result<()> {
+    if let Some(component) = component
+        && !components(cache).contains(&component)
+    {
+        bail!("Unknown install component `{}` (available components: {})", component, components(cache).join(", "));
+    }
+
+    for (dest, entry) in cache.installs.iter() {
+        if component.is_some_and(|component| entry.component() != component) {
+            continue;
+        }
+
+        let path = PathBuf::from(&cache.prefix).join(dest);
+        let abs_dest = destination(dest_dir, &path);
+
+        let (src, mode) = match entry {
+            InstallEntry::Symlink { symlink, .. } => {
+                install_symlink(symlink, &abs_dest).with_context(|| format!("Unable to install symlink `{}`", dest.to_string_lossy()))?;
+                files.push(InstalledFile {
+                    path,
+                    sha256: None,
+                    symlink: Some(symlink.clone()),
+                });
+                continue;
+            }
+            InstallEntry::Copy(src) => (src, None),
+            InstallEntry::File { source, mode, .. } => (source, *mode),
+        };
+
+        let abs_src = build_dir.join(src);
+        if !abs_src.exists() {
+            bail!("Unable to install `{}`, it does not exist", src.to_string_lossy());
+        }
+
+        if abs_src.is_dir() {
+            install_tree(&abs_src, &path, dest_dir, mode, files).with_context(|| format!("Unable to install directory `{}` to `{}`", src.to_string_lossy(), abs_dest.to_string_lossy()))?;
+            continue;
+        }
+
+        let sha256 = install_file(&abs_src, &abs_dest, mode).with_context(|| format!("Unable to install `{}` to `{}`", src.to_string_lossy(), abs_dest.to_string_lossy()))?;
+        files.push(InstalledFile {
+            path,
+            sha256: Some(sha256),
+            symlink: None,
+        });
+    }
+
+    Ok(())
+}
+
+pub fn install(build_dir: &Path, dest_dir: Option<String>, component: Option<String>) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+    let path = InstallManifest::path(build_dir, component.as_deref());
+
+    let mut files = Vec::new();
+    let result = stage(build_dir, &cache, dest_dir.as_deref(), component.as_deref(), &mut files);
+
+    // A failed installation records the files installed so far next to the ones of the previous
+    // installation, so that `uninstall` still finds all of them
+    if result.is_err()
+        && let Ok(Some(previous)) = InstallManifest::load(path.clone())
+    {
+        let previous: Vec<InstalledFile> = previous.files.into_iter().filter(|file| !files.iter().any(|installed| installed.path == file.path)).collect();
+        files.extend(previous);
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    let manifest = InstallManifest {
+        path,
+        version: CURRENT_VERSION,
+        prefix: cache.prefix.clone(),
+        files,
+    };
+    let written = manifest.update();
+    result.and(written)
+}
+
+pub fn uninstall(build_dir: &Path, dest_dir: Option<String>, component: Option<String>) -> Result<()> {
+    let mut manifest = match InstallManifest::load(InstallManifest::path(build_dir, component.as_deref()))? {
+        None => match component {
+            None => bail!("Nothing to uninstall, the install manifest does not exist"),
+            Some(component) => bail!("Nothing to uninstall, component `{}` is not installed", component),
+        },
+        Some(manifest) => manifest,
+    };
+
+    let root = destination(dest_dir.as_deref(), Path::new(&manifest.prefix));
+
+    let mut remaining = Vec::new();
+    for file in manifest.files {
+        let abs_path = destination(dest_dir.as_deref(), &file.path);
+
+        if symlink_metadata(&abs_path).is_err() {
+            println!("Skipping `{}`, it does not exist", abs_path.to_string_lossy());
+            continue;
+        }
+
+        let unchanged = match (&file.symlink, &file.sha256) {
+            (Some(symlink), _) => read_link(&abs_path).is_ok_and(|target| target == *symlink),
+            (None, Some(sha256)) => !abs_path.is_symlink() && sha256_file(&abs_path)? == *sha256,
+            (None, None) => false,
+        };
+
+        if !unchanged {
+            println!("Skipping `{}`, it was modified after the installation", abs_path.to_string_lossy());
+            remaining.push(file);
+            continue;
+        }
+
+        remove_file(&abs_path).with_context(|| format!("Failed to remove `{}`", abs_path.to_string_lossy()))?;
+        prune_empty_dirs(&abs_path, &root)?;
+    }
+
+    manifest.files = remaining;
+    manifest.update()
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    env::var,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Machine {
+    pub triple: Option<String>,
+    pub sysroot: Option<PathBuf>,
+
+    #[serde(default)]
+    pub binaries: HashMap<String, String>,
+    #[serde(skip)]
+    pub binary_args: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub flags: HashMap<String, Vec<String>>,
+}
+
+pub const ENVIRONMENT_VARIABLES: &[&str] = &["CC", "CFLAGS", "CPPFLAGS", "LDFLAGS", "LD", "AR"];
+
+pub fn environment() -> HashMap<String, String> {
+    let mut environment = HashMap::new();
+    for name in ENVIRONMENT_VARIABLES {
+        if let Ok(value) = var(name) {
+            environment.insert(name.to_string(), value);
+        }
+    }
+    environment
+}
+
+impl Machine {
+    // Values such as `ccache gcc` or `gcc -m32` name the command first, followed by leading arguments
+    fn split_binaries(&mut self) {
+        for (name, value) in self.binaries.iter_mut() {
+            let mut words = value.split_whitespace().map(String::from);
+            let command = words.next().unwrap_or_default();
+            let args: Vec<String> = words.collect();
+            if !args.is_empty() {
+                self.binary_args.insert(name.clone(), args);
+            }
+            *value = command;
+        }
+        self.binaries.retain(|_, command| !command.is_empty());
+    }
+
+    pub fn from_environment(environment: &HashMap<String, String>) -> Machine {
+        let mut machine = Machine::default();
+
+        for (name, binary) in [("CC", "c"), ("LD", "ld"), ("AR", "ar")] {
+            if let Some(value) = environment.get(name) {
+                machine.binaries.insert(binary.to_string(), value.clone());
+            }
+        }
+        machine.split_binaries();
+
+        for (names, flags) in [(["CPPFLAGS", "CFLAGS"].as_slice(), "c"), (["LDFLAGS"].as_slice(), "c_link")] {
+            let mut values = Vec::new();
+            for name in names {
+                if let Some(value) = environment.get(*name) {
+                    values.extend(value.split_whitespace().map(String::from));
+                }
+            }
+
+            if !values.is_empty() {
+                machine.flags.insert(flags.to_string(), values);
+            }
+        }
+
+        machine
+    }
+}
+
+pub struct Machines {
+    pub host: Machine,
+    pub target: Machine,
+    pub cross: bool,
+}
+
+impl Machines {
+    pub fn native(host: Machine) -> Machines {
+        Machines {
+            target: host.clone(),
+            host,
+            cross: false,
+        }
+    }
+
+    pub fn cross(host: Machine, target: Machine) -> Machines {
+        Machines { host, target, cross: true }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Machine> {
+        match name {
+            "host" => Some(&self.host),
+            "target" => Some(&self.target),
+            _ => None,
+        }
+    }
+}
+
+pub fn load_cross_file(path: &Path) -> Result<Machine> {
+    let cross_data = read_to_string(path).with_context(|| format!("Failed to read cross file `{}`", path.to_string_lossy()))?;
+    let mut machine: Machine = toml::from_str(&cross_data).with_context(|| format!("Failed to parse cross file `{}`", path.to_string_lossy()))?;
+
+    if let Some(triple) = &machine.triple
+        && !triple.chars().all(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.')
+    {
+        bail!("Target triple `{}` in cross file contains invalid characters", triple);
+    }
+
+    if let Some(sysroot) = &machine.sysroot
+        && sysroot.is_relative()
+    {
+        let cross_dir = match path.parent() {
+            None => bail!("Failed to resolve cross file directory"),
+            Some(cross_dir) => cross_dir,
+        };
+        machine.sysroot = Some(cross_dir.join(sysroot));
+    }
+
+    machine.split_binaries();
+    Ok(machine)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Machine;
+
+    #[test]
+    fn environment_binaries_with_arguments() {
+        let environment = HashMap::from([
+            (String::from("CC"), String::from("ccache gcc -m32")),
+            (String::from("LD"), String::from(" ld.lld ")),
+            (String::from("AR"), String::from("  ")),
+            (String::from("CFLAGS"), String::from("-O2  -g")),
+        ]);
+        let machine = Machine::from_environment(&environment);
+
+        assert_eq!(machine.binaries.get("c").map(String::as_str), Some("ccache"));
+        assert_eq!(machine.binary_args.get("c"), Some(&vec![String::from("gcc"), String::from("-m32")]));
+        assert_eq!(machine.binaries.get("ld").map(String::as_str), Some("ld.lld"));
+        assert!(!machine.binary_args.contains_key("ld"));
+        assert!(!machine.binaries.contains_key("ar"));
+        assert_eq!(machine.flags.get("c"), Some(&vec![String::from("-O2"), String::from("-g")]));
+    }
+}
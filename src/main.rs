@@ -1,23 +1,44 @@
 use std::{
-    fs::{copy, create_dir_all},
-    path::{Component, PathBuf},
-    process::Command,
+    collections::HashMap,
+    path::{PathBuf, absolute},
+    process::{Command, exit},
 };
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result};
 use clap::{Args, Parser, Subcommand};
 use which::which;
 
-use crate::{cache::FabricateCache, setup::setup};
+use crate::{
+    deps::{DependencyOptions, mirrors::Mirrors},
+    dist::DistOptions,
+    install::InstallOptions,
+    package::PackageFormat,
+    presets::load_preset,
+    sbom::SbomFormat,
+    setup::{resolve_config, setup},
+};
 
 mod cache;
+mod deps;
+mod dist;
+mod install;
+mod machine;
+mod package;
+mod presets;
+mod reproducible;
+mod sbom;
 mod setup;
+#[cfg(test)]
+mod testing;
+
+const DEFAULT_BUILD_DIR: &str = "build";
+const DEFAULT_PREFIX: &str = "/usr";
 
 #[derive(Parser)]
 #[command(version, next_line_help = true)]
 struct FabricateOptions {
-    #[arg(short, long, help = "path to build directory", default_value = "build", global = true)]
-    build_dir: String,
+    #[arg(short, long, help = "path to build directory (default: build)", global = true)]
+    build_dir: Option<String>,
 
     #[command(subcommand)]
     command: MainCommand,
@@ -26,19 +47,88 @@ struct FabricateOptions {
 #[derive(Subcommand)]
 enum MainCommand {
     #[command()]
-    Setup(SetupOpts),
+    Setup(Box<SetupOpts>),
 
     #[command()]
-    Build,
+    Build {
+        #[arg(help = "Ninja targets to build, such as `install` (default: all)")]
+        targets: Vec<String>,
+    },
 
     #[command()]
     Install(InstallOpts),
+
+    #[command(about = "Remove the files of the last install")]
+    Uninstall(UninstallOpts),
+
+    #[command(subcommand)]
+    Deps(DepsCommand),
+
+    #[command(about = "Copy the dependencies of the last setup into a vendor directory")]
+    Vendor {
+        #[arg(help = "Vendor directory (default: vendor in the project root)")]
+        dir: Option<PathBuf>,
+    },
+
+    #[command(about = "Install into a staging directory and archive it into a reproducible package")]
+    Package {
+        #[arg(long, value_enum, help = "Package format")]
+        format: PackageFormat,
+
+        #[arg(long, help = "Only package the entries of the given install component")]
+        component: Option<String>,
+    },
+
+    #[command(about = "Create a source archive of the project at HEAD and verify that it builds")]
+    Dist {
+        #[arg(long, help = "Include the vendored dependencies of the vendor directory")]
+        include_vendor: bool,
+
+        #[arg(long, help = "Include fab.lock from the working tree")]
+        include_lock: bool,
+
+        #[arg(long, help = "Skip unpacking, setting up and building the archive")]
+        no_verify: bool,
+    },
+
+    #[command(about = "Generate a software bill of materials from the last setup")]
+    Sbom {
+        #[arg(long, value_enum, help = "Document format")]
+        format: SbomFormat,
+
+        #[arg(long, short, help = "Write the document to a file instead of stdout")]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DepsCommand {
+    #[command(about = "List the dependencies of the last setup")]
+    List,
+
+    #[command(about = "Show the state of the dependency checkouts")]
+    Status,
+
+    #[command(about = "Resolve the latest commits of git dependencies and record them in fab.lock")]
+    Update {
+        #[arg(help = "Names of the dependencies to update (default: all)")]
+        names: Vec<String>,
+    },
+
+    #[command(about = "Remove checkouts of dependencies that are no longer declared")]
+    Clean {
+        #[arg(long, help = "Also remove checkouts with uncommitted changes or local commits")]
+        force: bool,
+    },
 }
 
 #[derive(Args)]
 struct SetupOpts {
-    #[arg(long, help = "Installation prefix (default: /usr)", default_value = "/usr")]
-    prefix: String,
+    #[arg(long, help = "Installation prefix (default: /usr)")]
+    prefix: Option<String>,
+
+    #[command(flatten)]
+    install_dirs: InstallDirOpts,
 
     #[arg(long, help = "Fabricate configuration file path (default: fab.lua)", default_value = "fab.lua")]
     config: String,
@@ -48,12 +138,111 @@ struct SetupOpts {
 
     #[arg(long, value_parser = keyvalue_opt_validate, help = "Override a git dependency in the format of <dependency name>=<path>")]
     dependency_override: Vec<(String, String)>,
+
+    #[arg(long, help = "Discard local modifications in git dependency checkouts when updating them")]
+    force_deps: bool,
+
+    #[arg(long, help = "Fail instead of accessing the network when a dependency is not available locally")]
+    offline: bool,
+
+    #[arg(long, help = "Directory of vendored dependencies (default: vendor in the project root)")]
+    vendor_dir: Option<PathBuf>,
+
+    #[arg(long, help = "Also generate ninja `install` targets that build and install out of date files")]
+    install_targets: bool,
+
+    #[arg(long, help = "Cross compilation file describing the target machine")]
+    cross_file: Option<String>,
+
+    #[arg(long, help = "Apply a named preset from fab-presets.toml, explicitly passed flags take precedence")]
+    preset: Option<String>,
+}
+
+#[derive(Args)]
+#[command(next_help_heading = "Installation directories")]
+struct InstallDirOpts {
+    #[arg(long, help = "User executables, relative to the prefix (default: bin)")]
+    bindir: Option<String>,
+
+    #[arg(long, help = "System administrator executables, relative to the prefix (default: sbin)")]
+    sbindir: Option<String>,
+
+    #[arg(long, help = "Libraries, relative to the prefix (default: lib)")]
+    libdir: Option<String>,
+
+    #[arg(long, help = "Executables run by other programs, relative to the prefix (default: libexec)")]
+    libexecdir: Option<String>,
+
+    #[arg(long, help = "C header files, relative to the prefix (default: include)")]
+    includedir: Option<String>,
+
+    #[arg(long, help = "Read-only configuration files, relative to the prefix (default: etc)")]
+    sysconfdir: Option<String>,
+
+    #[arg(long, help = "Modifiable host-specific data, relative to the prefix (default: var)")]
+    localstatedir: Option<String>,
+
+    #[arg(long, help = "Read-only architecture-independent data root, relative to the prefix (default: share)")]
+    datarootdir: Option<String>,
+
+    #[arg(long, help = "Read-only architecture-independent data, relative to the prefix (default: DATAROOTDIR)")]
+    datadir: Option<String>,
+
+    #[arg(long, help = "Manual pages, relative to the prefix (default: DATAROOTDIR/man)")]
+    mandir: Option<String>,
+
+    #[arg(long, help = "Info documentation, relative to the prefix (default: DATAROOTDIR/info)")]
+    infodir: Option<String>,
+
+    #[arg(long, help = "Documentation, relative to the prefix (default: DATAROOTDIR/doc)")]
+    docdir: Option<String>,
+
+    #[arg(long, help = "Locale-dependent data, relative to the prefix (default: DATAROOTDIR/locale)")]
+    localedir: Option<String>,
+}
+
+impl InstallDirOpts {
+    fn overrides(self) -> Vec<(String, String)> {
+        [
+            ("bindir", self.bindir),
+            ("sbindir", self.sbindir),
+            ("libdir", self.libdir),
+            ("libexecdir", self.libexecdir),
+            ("includedir", self.includedir),
+            ("sysconfdir", self.sysconfdir),
+            ("localstatedir", self.localstatedir),
+            ("datarootdir", self.datarootdir),
+            ("datadir", self.datadir),
+            ("mandir", self.mandir),
+            ("infodir", self.infodir),
+            ("docdir", self.docdir),
+            ("localedir", self.localedir),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name.to_string(), value)))
+        .collect()
+    }
 }
 
 #[derive(Args)]
 struct InstallOpts {
     #[arg(long, help = "Specify the destdir of the install", env = "DESTDIR")]
     dest_dir: Option<String>,
+
+    #[arg(long, help = "Only install the entries of the given component")]
+    component: Option<String>,
+
+    #[arg(long, help = "List the install components and exit", conflicts_with = "component")]
+    list_components: bool,
+}
+
+#[derive(Args)]
+struct UninstallOpts {
+    #[arg(long, help = "Specify the destdir of the install", env = "DESTDIR")]
+    dest_dir: Option<String>,
+
+    #[arg(long, help = "Only uninstall the files of the given component")]
+    component: Option<String>,
 }
 
 fn keyvalue_opt_validate(s: &str) -> Result<(String, String), String> {
@@ -63,59 +252,113 @@ fn keyvalue_opt_validate(s: &str) -> Result<(String, String), String> {
     }
 }
 
+fn resolve_build_dir(build_dir: Option<String>) -> Result<PathBuf> {
+    PathBuf::from(build_dir.unwrap_or(String::from(DEFAULT_BUILD_DIR)))
+        .canonicalize()
+        .context("Failed to resolve build directory path")
+}
+
 fn main() -> Result<()> {
     let opts = FabricateOptions::parse();
 
     match opts.command {
-        MainCommand::Setup(setup_opts) => setup(setup_opts.config, opts.build_dir, setup_opts.prefix, setup_opts.option, setup_opts.dependency_override)?,
-        MainCommand::Build => {
-            let ninja_path = which("ninja").context("Failed to locate ninja, cannot build")?;
-            Command::new(ninja_path).arg("-C").arg(opts.build_dir).status()?;
-        }
-        MainCommand::Install(install_opts) => {
-            let build_dir = PathBuf::from(opts.build_dir).canonicalize().context("Failed to resolve build directory path")?;
+        MainCommand::Setup(mut setup_opts) => {
+            let mut build_dir = opts.build_dir;
+            let mut install_dirs = setup_opts.install_dirs.overrides();
 
-            let cache = match FabricateCache::load(&build_dir.join("fabricate_cache.toml"))? {
-                None => bail!("Cache is not initialized, make sure the build directory path is correct"),
-                Some(cache) => cache,
-            };
+            if let Some(preset_name) = &setup_opts.preset {
+                let (_, config_dir) = resolve_config(&setup_opts.config)?;
+                let preset = load_preset(&config_dir, preset_name)?;
 
-            for (dest, src) in cache.installs {
-                let abs_src = build_dir.join(&src);
-                let mut abs_dest = PathBuf::from(&cache.prefix).join(&dest);
-
-                if let Some(dest_dir) = &install_opts.dest_dir {
-                    let mut joined = PathBuf::from(dest_dir);
+                build_dir = build_dir.or(preset.build_dir(&config_dir));
+                setup_opts.prefix = setup_opts.prefix.or(preset.prefix.clone());
+                setup_opts.cross_file = setup_opts.cross_file.or(preset.cross_file(&config_dir));
+                setup_opts.option.splice(0..0, preset.options()?);
+                setup_opts.dependency_override.splice(0..0, preset.overrides);
+                install_dirs.splice(0..0, preset.dirs);
+            }
 
-                    for c in abs_dest.components() {
-                        match c {
-                            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
-                            Component::ParentDir => joined.push(".."),
-                            Component::Normal(part) => joined.push(part),
-                        }
-                    }
+            setup(
+                setup_opts.config,
+                absolute(build_dir.unwrap_or(String::from(DEFAULT_BUILD_DIR))).context("Failed to resolve build directory path")?,
+                InstallOptions {
+                    prefix: setup_opts.prefix.unwrap_or(String::from(DEFAULT_PREFIX)),
+                    dirs: install_dirs,
+                    targets: setup_opts.install_targets,
+                },
+                setup_opts.option,
+                DependencyOptions {
+                    overrides: HashMap::from_iter(setup_opts.dependency_override),
+                    force: setup_opts.force_deps,
+                    offline: setup_opts.offline,
+                    mirrors: Mirrors::load()?,
+                    vendor_dir: setup_opts.vendor_dir.map(absolute).transpose().context("Failed to resolve vendor directory path")?,
+                },
+                setup_opts.cross_file,
+            )?
+        }
+        MainCommand::Build { targets } => {
+            let ninja_path = which("ninja").context("Failed to locate ninja, cannot build")?;
+            let status = Command::new(ninja_path).arg("-C").arg(resolve_build_dir(opts.build_dir)?).args(targets).status()?;
+            if !status.success() {
+                exit(status.code().unwrap_or(1));
+            }
+        }
+        MainCommand::Deps(deps_command) => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                    abs_dest = joined;
-                }
+            match deps_command {
+                DepsCommand::List => deps::list(&build_dir)?,
+                DepsCommand::Status => deps::status(&build_dir)?,
+                DepsCommand::Update { names } => deps::update(&build_dir, names)?,
+                DepsCommand::Clean { force } => deps::clean(&build_dir, force)?,
+            }
+        }
+        MainCommand::Vendor { dir } => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                if !abs_src.exists() {
-                    bail!("Unable to install artifact `{}`, it does not exist", src.to_string_lossy());
-                }
+            deps::vendor::vendor(&build_dir, dir)?;
+        }
+        MainCommand::Package { format, component } => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                if abs_src.is_dir() {
-                    bail!("Unable to install artifact `{}`, it is a directory", src.to_string_lossy());
-                }
+            package::package(&build_dir, format, component)?;
+        }
+        MainCommand::Dist {
+            include_vendor,
+            include_lock,
+            no_verify,
+        } => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                let dest_dir = match abs_dest.parent() {
-                    None => bail!("Could not resolve directory path of destination"),
-                    Some(path) => path,
-                };
+            dist::dist(
+                &build_dir,
+                DistOptions {
+                    include_vendor,
+                    include_lock,
+                    verify: !no_verify,
+                },
+            )?;
+        }
+        MainCommand::Sbom { format, output } => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                create_dir_all(dest_dir).with_context(|| format!("Unable to install artifact `{}`, could not create dest dir `{}`", src.to_string_lossy(), dest_dir.to_string_lossy()))?;
+            sbom::sbom(&build_dir, format, output)?;
+        }
+        MainCommand::Install(install_opts) => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
 
-                copy(&src, &abs_dest).with_context(|| format!("Unable to install artifact `{}` to `{}`, could not copy", src.to_string_lossy(), abs_dest.to_string_lossy()))?;
+            if install_opts.list_components {
+                install::list_components(&build_dir)?;
+            } else {
+                install::install(&build_dir, install_opts.dest_dir, install_opts.component)?;
             }
         }
+        MainCommand::Uninstall(uninstall_opts) => {
+            let build_dir = resolve_build_dir(opts.build_dir)?;
+
+            install::uninstall(&build_dir, uninstall_opts.dest_dir, uninstall_opts.component)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,337 @@
+use std::{
+    fs::{File, Metadata, create_dir_all, exists, read_link, remove_dir_all, symlink_metadata},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use flate2::{Compression, GzBuilder};
+use tar::{Builder, EntryType, Header};
+use walkdir::WalkDir;
+
+use crate::{cache::FabricateCache, install::stage, reproducible::source_date_epoch};
+
+const STAGING_DIR: &str = "package-staging";
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PackageFormat {
+    #[value(name = "tar.gz")]
+    TarGz,
+    #[value(name = "tar.zst")]
+    TarZst,
+    #[value(name = "cpio")]
+    Cpio,
+}
+
+impl PackageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            PackageFormat::TarGz => "tar.gz",
+            PackageFormat::TarZst => "tar.zst",
+            PackageFormat::Cpio => "cpio",
+        }
+    }
+}
+
+pub enum PackageEntryKind {
+    Directory,
+    File(PathBuf),
+    Data(Vec<u8>),
+    Symlink(PathBuf),
+}
+
+pub struct PackageEntry {
+    pub path: String,
+    pub mode: u32,
+    pub size: u64,
+    pub kind: PackageEntryKind,
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_: &Metadata) -> u32 {
+    0o644
+}
+
+pub fn collect_entries(root: &Path) -> Result<Vec<PackageEntry>> {
+    let mut entries = Vec::new();
+
+    for entry in WalkDir::new(root).min_depth(1).sort_by_file_name() {
+        let entry = entry.context("Failed to read staging directory")?;
+        let relative = entry.path().strip_prefix(root).context("Failed to resolve path in staging directory")?;
+
+        // Archive paths always use forward slashes
+        let path = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        let metadata = symlink_metadata(entry.path()).with_context(|| format!("Failed to read `{}`", entry.path().to_string_lossy()))?;
+
+        entries.push(if metadata.is_dir() {
+            PackageEntry {
+                path,
+                mode: 0o755,
+                size: 0,
+                kind: PackageEntryKind::Directory,
+            }
+        } else if metadata.is_symlink() {
+            let target = read_link(entry.path()).with_context(|| format!("Failed to read link `{}`", entry.path().to_string_lossy()))?;
+            PackageEntry {
+                path,
+                mode: 0o777,
+                size: target.as_os_str().len() as u64,
+                kind: PackageEntryKind::Symlink(target),
+            }
+        } else {
+            PackageEntry {
+                path,
+                mode: file_mode(&metadata),
+                size: metadata.len(),
+                kind: PackageEntryKind::File(entry.path().to_path_buf()),
+            }
+        });
+    }
+
+    Ok(entries)
+}
+
+pub fn write_tar<W: Write>(writer: W, entries: &[PackageEntry], mtime: u64) -> Result<W> {
+    let mut builder = Builder::new(writer);
+    builder.follow_symlinks(false);
+
+    for entry in entries {
+        let mut header = Header::new_gnu();
+        header.set_mode(entry.mode);
+        header.set_mtime(mtime);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_username("root").context("Failed to set tar entry owner")?;
+        header.set_groupname("root").context("Failed to set tar entry group")?;
+
+        match &entry.kind {
+            PackageEntryKind::Directory => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_size(0);
+                builder.append_data(&mut header, format!("{}/", entry.path), io::empty())
+            }
+            PackageEntryKind::Symlink(target) => {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, &entry.path, target)
+            }
+            PackageEntryKind::File(source) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(entry.size);
+                let file = File::open(source).with_context(|| format!("Failed to open `{}`", source.to_string_lossy()))?;
+                builder.append_data(&mut header, &entry.path, file)
+            }
+            PackageEntryKind::Data(data) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_size(entry.size);
+                builder.append_data(&mut header, &entry.path, data.as_slice())
+            }
+        }
+        .with_context(|| format!("Failed to add `{}` to the package", entry.path))?;
+    }
+
+    builder.into_inner().context("Failed to finish tar archive")
+}
+
+fn write_cpio_header<W: Write>(writer: &mut W, ino: u64, mode: u32, nlink: u32, mtime: u64, size: u64, name: &str) -> Result<()> {
+    // New ASCII (newc) format, see cpio(5), the header fields are limited to 32 bits
+    for (field, value) in [("inode number", ino), ("modification time", mtime), ("size", size)] {
+        if value > u32::MAX as u64 {
+            bail!("The {} of `{}` ({}) does not fit into a cpio archive, use a tar format instead", field, name, value);
+        }
+    }
+
+    let header = format!(
+        "070701{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}{:08x}",
+        ino,
+        mode,
+        0,
+        0,
+        nlink,
+        mtime,
+        size,
+        0,
+        0,
+        0,
+        0,
+        name.len() + 1,
+        0
+    );
+    writer.write_all(header.as_bytes())?;
+    writer.write_all(name.as_bytes())?;
+    writer.write_all(&[0])?;
+    write_cpio_padding(writer, (header.len() + name.len() + 1) as u64)
+}
+
+fn write_cpio_padding<W: Write>(writer: &mut W, len: u64) -> Result<()> {
+    let padding = (4 - len % 4) % 4;
+    writer.write_all(&[0; 3][..padding as usize])?;
+    Ok(())
+}
+
+fn write_cpio<W: Write>(writer: &mut W, entries: &[PackageEntry], mtime: u64) -> Result<()> {
+    for (i, entry) in entries.iter().enumerate() {
+        let ino = i as u64 + 1;
+        match &entry.kind {
+            PackageEntryKind::Directory => write_cpio_header(writer, ino, 0o040000 | entry.mode, 2, mtime, 0, &entry.path)?,
+            PackageEntryKind::Symlink(target) => {
+                write_cpio_header(writer, ino, 0o120000 | entry.mode, 1, mtime, entry.size, &entry.path)?;
+                writer.write_all(target.as_os_str().as_encoded_bytes())?;
+                write_cpio_padding(writer, entry.size)?;
+            }
+            PackageEntryKind::File(source) => {
+                write_cpio_header(writer, ino, 0o100000 | entry.mode, 1, mtime, entry.size, &entry.path)?;
+                let mut file = File::open(source).with_context(|| format!("Failed to open `{}`", source.to_string_lossy()))?;
+                let copied = io::copy(&mut file, writer).with_context(|| format!("Failed to add `{}` to the package", entry.path))?;
+                if copied != entry.size {
+                    bail!("`{}` changed while creating the package", source.to_string_lossy());
+                }
+                write_cpio_padding(writer, entry.size)?;
+            }
+            PackageEntryKind::Data(data) => {
+                write_cpio_header(writer, ino, 0o100000 | entry.mode, 1, mtime, entry.size, &entry.path)?;
+                writer.write_all(data)?;
+                write_cpio_padding(writer, entry.size)?;
+            }
+        }
+    }
+
+    write_cpio_header(writer, 0, 0, 1, 0, 0, "TRAILER!!!")
+}
+
+fn write_package<W: Write>(writer: &mut W, format: PackageFormat, entries: &[PackageEntry], mtime: u64) -> Result<()> {
+    match format {
+        PackageFormat::TarGz => {
+            let encoder = GzBuilder::new().mtime(0).write(writer, Compression::best());
+            write_tar(encoder, entries, mtime)?.finish().context("Failed to finish gzip stream")?;
+        }
+        PackageFormat::TarZst => {
+            let encoder = zstd::Encoder::new(writer, 19).context("Failed to create zstd encoder")?;
+            write_tar(encoder, entries, mtime)?.finish().context("Failed to finish zstd stream")?;
+        }
+        PackageFormat::Cpio => write_cpio(writer, entries, mtime)?,
+    }
+    Ok(())
+}
+
+pub fn package(build_dir: &Path, format: PackageFormat, component: Option<String>) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    let staging_dir = build_dir.join(STAGING_DIR);
+    if exists(&staging_dir)? {
+        remove_dir_all(&staging_dir).context("Failed to remove previous staging directory")?;
+    }
+    create_dir_all(&staging_dir).context("Failed to create staging directory")?;
+
+    stage(build_dir, &cache, Some(&staging_dir.to_string_lossy()), component.as_deref(), &mut Vec::new())?;
+    let entries = collect_entries(&staging_dir)?;
+
+    // Without SOURCE_DATE_EPOCH all entries use the unix epoch to keep the package reproducible
+    let mtime = source_date_epoch()?.unwrap_or(0);
+
+    let mut name = match cache.project_dir.file_name() {
+        None => String::from("package"),
+        Some(name) => name.to_string_lossy().to_string(),
+    };
+    if let Some(component) = &component {
+        name = format!("{}-{}", name, component);
+    }
+    let output = build_dir.join(format!("{}.{}", name, format.extension()));
+
+    let file = File::create(&output).with_context(|| format!("Failed to create `{}`", output.to_string_lossy()))?;
+    let mut writer = BufWriter::new(file);
+    write_package(&mut writer, format, &entries, mtime)?;
+    writer.flush().with_context(|| format!("Failed to write `{}`", output.to_string_lossy()))?;
+
+    remove_dir_all(&staging_dir).context("Failed to remove staging directory")?;
+
+    println!("Created package `{}`", output.to_string_lossy());
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{
+        fs::{File, create_dir_all, write},
+        os::unix::fs::symlink,
+        path::Path,
+        time::{Duration, SystemTime},
+    };
+
+    use tar::Archive;
+
+    use super::{PackageFormat, collect_entries, write_cpio_header, write_package};
+    use crate::testing::TestDir;
+
+    fn staging_dir(name: &str) -> TestDir {
+        let dir = TestDir::new(name);
+        create_dir_all(dir.join("usr/bin")).unwrap();
+        create_dir_all(dir.join("usr/lib")).unwrap();
+        write(dir.join("usr/bin/app"), "binary").unwrap();
+        write(dir.join("usr/lib/libk.so.1"), "library").unwrap();
+        symlink("libk.so.1", dir.join("usr/lib/libk.so")).unwrap();
+        dir
+    }
+
+    fn touch(dir: &Path, time: SystemTime) {
+        for path in ["usr/bin/app", "usr/lib/libk.so.1"] {
+            File::options().write(true).open(dir.join(path)).unwrap().set_modified(time).unwrap();
+        }
+    }
+
+    fn package_bytes(dir: &Path, format: PackageFormat) -> Vec<u8> {
+        let mut output = Vec::new();
+        write_package(&mut output, format, &collect_entries(dir).unwrap(), 0).unwrap();
+        output
+    }
+
+    #[test]
+    fn package_is_reproducible() {
+        let first = staging_dir("package-first");
+        let second = staging_dir("package-second");
+        touch(&first, SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000));
+        touch(&second, SystemTime::now());
+
+        for format in [PackageFormat::TarGz, PackageFormat::TarZst, PackageFormat::Cpio] {
+            assert!(package_bytes(&first, format) == package_bytes(&second, format), "{} output differs", format.extension());
+        }
+
+        let tar = package_bytes(&first, PackageFormat::TarGz);
+        let mut archive = Archive::new(flate2::read::GzDecoder::new(tar.as_slice()));
+        let mut paths = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            assert_eq!((header.mtime().unwrap(), header.uid().unwrap(), header.gid().unwrap()), (0, 0, 0));
+            paths.push(entry.path().unwrap().to_string_lossy().to_string());
+        }
+        assert_eq!(paths, ["usr/", "usr/bin/", "usr/bin/app", "usr/lib/", "usr/lib/libk.so", "usr/lib/libk.so.1"]);
+    }
+
+    #[test]
+    fn cpio_rejects_large_fields() {
+        let large = u32::MAX as u64 + 1;
+        let size_err = write_cpio_header(&mut Vec::new(), 1, 0o100644, 1, 0, large, "usr/lib/big").unwrap_err();
+        assert_eq!(
+            size_err.to_string(),
+            "The size of `usr/lib/big` (4294967296) does not fit into a cpio archive, use a tar format instead"
+        );
+
+        let mtime_err = write_cpio_header(&mut Vec::new(), 1, 0o100644, 1, large, 0, "usr/lib/big").unwrap_err();
+        assert_eq!(
+            mtime_err.to_string(),
+            "The modification time of `usr/lib/big` (4294967296) does not fit into a cpio archive, use a tar format instead"
+        );
+
+        let mut output = Vec::new();
+        write_cpio_header(&mut output, u32::MAX as u64, 0o100644, 1, u32::MAX as u64, u32::MAX as u64, "usr/lib/big").unwrap();
+        assert_eq!(&output[6..14], b"ffffffff");
+    }
+}
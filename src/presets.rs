@@ -0,0 +1,83 @@
+use std::{
+    collections::HashMap,
+    fs::{exists, read_to_string},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+pub const PRESETS_FILE: &str = "fab-presets.toml";
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Preset {
+    pub prefix: Option<String>,
+    build_dir: Option<String>,
+    cross_file: Option<String>,
+
+    #[serde(default)]
+    pub dirs: HashMap<String, String>,
+
+    #[serde(default)]
+    options: HashMap<String, toml::Value>,
+    #[serde(default)]
+    pub overrides: HashMap<String, String>,
+}
+
+impl Preset {
+    pub fn build_dir(&self, config_dir: &Path) -> Option<String> {
+        self.build_dir.as_ref().map(|build_dir| config_dir.join(build_dir).to_string_lossy().to_string())
+    }
+
+    pub fn cross_file(&self, config_dir: &Path) -> Option<String> {
+        self.cross_file.as_ref().map(|cross_file| config_dir.join(cross_file).to_string_lossy().to_string())
+    }
+
+    pub fn options(&self) -> Result<Vec<(String, String)>> {
+        let mut options = Vec::new();
+        for (key, value) in self.options.iter() {
+            options.push((key.clone(), option_value(key, value)?));
+        }
+        Ok(options)
+    }
+}
+
+fn option_value(key: &str, value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(str) => str.clone(),
+        toml::Value::Integer(int) => int.to_string(),
+        toml::Value::Float(float) => float.to_string(),
+        toml::Value::Boolean(bool) => bool.to_string(),
+        toml::Value::Array(values) => {
+            let mut elements = Vec::new();
+            for value in values {
+                match value {
+                    toml::Value::Array(_) | toml::Value::Table(_) => bail!("Preset option `{}` contains a nested array or table", key),
+                    value => elements.push(option_value(key, value)?),
+                }
+            }
+            elements.join(",")
+        }
+        toml::Value::Datetime(_) | toml::Value::Table(_) => bail!("Preset option `{}` has an unsupported type `{}`", key, value.type_str()),
+    })
+}
+
+pub fn load_preset(config_dir: &Path, name: &str) -> Result<Preset> {
+    let presets_path = config_dir.join(PRESETS_FILE);
+    if !exists(&presets_path)? {
+        bail!("Preset `{}` requested but `{}` does not exist", name, presets_path.to_string_lossy());
+    }
+
+    let presets_data = read_to_string(&presets_path).with_context(|| format!("Failed to read `{}`", presets_path.to_string_lossy()))?;
+    let mut presets: HashMap<String, Preset> = toml::from_str(&presets_data).with_context(|| format!("Failed to parse `{}`", presets_path.to_string_lossy()))?;
+
+    match presets.remove(name) {
+        None => {
+            let mut names: Vec<String> = presets.into_keys().collect();
+            names.sort();
+            bail!("Preset `{}` is not defined in `{}` (available presets: {})", name, PRESETS_FILE, names.join(", "))
+        }
+        Some(preset) => Ok(preset),
+    }
+}
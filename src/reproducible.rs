@@ -0,0 +1,10 @@
+use std::env::var;
+
+use anyhow::{Context, Result};
+
+pub fn source_date_epoch() -> Result<Option<u64>> {
+    match var("SOURCE_DATE_EPOCH") {
+        Err(_) => Ok(None),
+        Ok(epoch) => Ok(Some(epoch.trim().parse::<u64>().context("SOURCE_DATE_EPOCH is not a valid timestamp")?)),
+    }
+}
@@ -0,0 +1,273 @@
+use std::{
+    fs::write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::{cache::FabricateCache, reproducible::source_date_epoch};
+
+const VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SbomFormat {
+    Spdx,
+    Cyclonedx,
+}
+
+fn timestamp() -> Result<u64> {
+    if let Some(epoch) = source_date_epoch()? {
+        return Ok(epoch);
+    }
+
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH).context("System time is before the unix epoch")?.as_secs())
+}
+
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+fn document_uuid(cache: &FabricateCache) -> Result<String> {
+    let cache_data = toml::to_string(cache).context("Failed to serialize fabricate cache")?;
+    let mut hash = Sha256::digest(cache_data.as_bytes())[..16].to_vec();
+    hash[6] = (hash[6] & 0x0f) | 0x80;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+
+    let hex: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+fn spdx_id(kind: &str, name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '-' }).collect();
+    format!("SPDXRef-{}-{}", kind, name)
+}
+
+fn tool_name(path: &Path) -> String {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().to_string()
+}
+
+// Tools are only asked for their version here instead of during every setup, a tool that does not
+// answer within the timeout is listed without one
+fn tool_version(path: &Path) -> Option<String> {
+    let mut child = Command::new(path).arg("--version").stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().ok()?;
+
+    let start = Instant::now();
+    while child.try_wait().ok()?.is_none() {
+        if start.elapsed() >= VERSION_TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            return None;
+        }
+        sleep(Duration::from_millis(10));
+    }
+
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout.lines().chain(stderr.lines()).map(str::trim).find(|line| !line.is_empty()).map(String::from)
+}
+
+fn spdx(cache: &FabricateCache, project: &str, created: &str, uuid: &str) -> Value {
+    let project_id = spdx_id("Project", project);
+    let mut packages = vec![json!({
+        "name": project,
+        "SPDXID": project_id,
+        "downloadLocation": "NOASSERTION",
+        "filesAnalyzed": false,
+    })];
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": project_id,
+    })];
+
+    for dep in cache.git_dependencies.iter() {
+        let id = spdx_id("Git", &dep.name);
+        let mut package = json!({
+            "name": dep.name,
+            "SPDXID": id,
+            "versionInfo": dep.commit.as_ref().unwrap_or(&dep.revision),
+            "downloadLocation": match &dep.commit {
+                None => format!("git+{}", dep.url),
+                Some(commit) => format!("git+{}@{}", dep.url, commit),
+            },
+            "filesAnalyzed": false,
+        });
+        if let Some(override_path) = &dep.override_path {
+            package["comment"] = json!(format!("Overridden by {}", override_path.to_string_lossy()));
+        }
+
+        packages.push(package);
+        relationships.push(json!({ "spdxElementId": project_id, "relationshipType": "DEPENDS_ON", "relatedSpdxElement": id }));
+    }
+
+    for dep in cache.archive_dependencies.iter() {
+        let id = spdx_id("Archive", &dep.name);
+        let mut package = json!({
+            "name": dep.name,
+            "SPDXID": id,
+            "downloadLocation": dep.url,
+            "filesAnalyzed": false,
+            "checksums": [{ "algorithm": "SHA256", "checksumValue": dep.sha256 }],
+        });
+        if let Some(override_path) = &dep.override_path {
+            package["comment"] = json!(format!("Overridden by {}", override_path.to_string_lossy()));
+        }
+
+        packages.push(package);
+        relationships.push(json!({ "spdxElementId": project_id, "relationshipType": "DEPENDS_ON", "relatedSpdxElement": id }));
+    }
+
+    for (i, tool) in cache.tools.iter().enumerate() {
+        let id = spdx_id("Tool", &format!("{}-{}", i, tool_name(&tool.path)));
+        let mut package = json!({
+            "name": tool_name(&tool.path),
+            "SPDXID": id,
+            "downloadLocation": "NOASSERTION",
+            "filesAnalyzed": false,
+            "comment": format!("Resolved to {}", tool.path.to_string_lossy()),
+        });
+        if let Some(version) = tool_version(&tool.path) {
+            package["versionInfo"] = json!(version);
+        }
+
+        packages.push(package);
+        relationships.push(json!({ "spdxElementId": id, "relationshipType": "BUILD_TOOL_OF", "relatedSpdxElement": project_id }));
+    }
+
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": project,
+        "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", project, uuid),
+        "creationInfo": {
+            "created": created,
+            "creators": [format!("Tool: fabricate-{}", env!("CARGO_PKG_VERSION"))],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+fn cyclonedx(cache: &FabricateCache, project: &str, created: &str, uuid: &str) -> Value {
+    let mut components = Vec::new();
+    let mut depends_on = Vec::new();
+
+    for dep in cache.git_dependencies.iter() {
+        let bom_ref = format!("git:{}", dep.name);
+        let mut properties = vec![json!({ "name": "fabricate:revision", "value": dep.revision })];
+        if let Some(override_path) = &dep.override_path {
+            properties.push(json!({ "name": "fabricate:override", "value": override_path.to_string_lossy() }));
+        }
+
+        components.push(json!({
+            "type": "library",
+            "bom-ref": bom_ref,
+            "name": dep.name,
+            "version": dep.commit.as_ref().unwrap_or(&dep.revision),
+            "externalReferences": [{ "type": "vcs", "url": dep.url }],
+            "properties": properties,
+        }));
+        depends_on.push(bom_ref);
+    }
+
+    for dep in cache.archive_dependencies.iter() {
+        let bom_ref = format!("archive:{}", dep.name);
+        let mut component = json!({
+            "type": "library",
+            "bom-ref": bom_ref,
+            "name": dep.name,
+            "hashes": [{ "alg": "SHA-256", "content": dep.sha256 }],
+            "externalReferences": [{ "type": "distribution", "url": dep.url }],
+        });
+        if let Some(override_path) = &dep.override_path {
+            component["properties"] = json!([{ "name": "fabricate:override", "value": override_path.to_string_lossy() }]);
+        }
+
+        components.push(component);
+        depends_on.push(bom_ref);
+    }
+
+    for (i, tool) in cache.tools.iter().enumerate() {
+        let mut component = json!({
+            "type": "application",
+            "bom-ref": format!("tool:{}:{}", i, tool_name(&tool.path)),
+            "name": tool_name(&tool.path),
+            "scope": "excluded",
+            "properties": [
+                { "name": "fabricate:role", "value": "build-tool" },
+                { "name": "fabricate:path", "value": tool.path.to_string_lossy() },
+            ],
+        });
+        if let Some(version) = tool_version(&tool.path) {
+            component["version"] = json!(version);
+        }
+
+        components.push(component);
+    }
+
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", uuid),
+        "version": 1,
+        "metadata": {
+            "timestamp": created,
+            "tools": {
+                "components": [{ "type": "application", "name": "fabricate", "version": env!("CARGO_PKG_VERSION") }],
+            },
+            "component": { "type": "application", "bom-ref": "project", "name": project },
+        },
+        "components": components,
+        "dependencies": [{ "ref": "project", "dependsOn": depends_on }],
+    })
+}
+
+pub fn sbom(build_dir: &Path, format: SbomFormat, output: Option<PathBuf>) -> Result<()> {
+    let cache = FabricateCache::open(build_dir)?;
+
+    let project = match cache.project_dir.file_name() {
+        None => String::from("project"),
+        Some(name) => name.to_string_lossy().to_string(),
+    };
+    let created = format_timestamp(timestamp()?);
+    let uuid = document_uuid(&cache)?;
+
+    let document = match format {
+        SbomFormat::Spdx => spdx(&cache, &project, &created, &uuid),
+        SbomFormat::Cyclonedx => cyclonedx(&cache, &project, &created, &uuid),
+    };
+    let document = serde_json::to_string_pretty(&document).context("Failed to serialize SBOM")?;
+
+    match output {
+        None => println!("{}", document),
+        Some(output) => write(&output, format!("{}\n", document)).with_context(|| format!("Failed to write `{}`", output.to_string_lossy()))?,
+    }
+
+    Ok(())
+}
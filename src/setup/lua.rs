@@ -1,7 +1,3 @@
-use git2::{
-    FetchOptions,
-    build::{CheckoutBuilder, RepoBuilder},
-};
 use globset::{GlobBuilder, GlobSetBuilder};
 use mlua::{Error, ErrorContext, FromLua, Lua, Result, Table, UserData, UserDataRef, Value, Variadic};
 use pathdiff::diff_paths;
@@ -9,21 +5,103 @@ use regex::{Captures, Regex};
 use std::{
     cell::RefCell,
     collections::HashMap,
-    fs::{exists, remove_dir_all},
-    path::PathBuf,
+    fs::{create_dir_all, exists, read, write},
+    path::{Path, PathBuf},
     rc::Rc,
 };
 use walkdir::WalkDir;
 use which::which;
 
-use crate::cache::{FabricateCache, GitDependency};
+use crate::{
+    cache::{ArchiveDependency, FabricateCache, GitDependency, InstallEntry},
+    deps::{
+        DependencyOptions,
+        archive::{self, ArchiveFormat},
+        git::{self, GitOptions},
+        is_local_url,
+        lock::{LockedGit, Lockfile},
+        mirrors::Mirrors,
+        sha256_file,
+        vendor::{VENDOR_DIR, VendorManifest},
+    },
+    machine::Machines,
+};
+
+struct Project {
+    root: PathBuf,
+    options: HashMap<String, String>,
+    output_dir: PathBuf,
+}
+
+type ProjectStack = Rc<RefCell<Vec<Rc<Project>>>>;
+
+fn current_project(projects: &ProjectStack) -> Rc<Project> {
+    Rc::clone(projects.borrow().last().expect("project stack is empty"))
+}
 
 struct FabricateAppData {
     builds: Rc<RefCell<Vec<Build>>>,
+    written_files: Rc<RefCell<Vec<PathBuf>>>,
+    projects: ProjectStack,
 }
 
 pub struct ConfigResult {
-    install: HashMap<PathBuf, PathBuf>,
+    install: HashMap<PathBuf, InstallEntry>,
+}
+
+fn install_mode(value: Value) -> Result<u32> {
+    let mode = match value {
+        // A number such as 755 would be read as decimal, so only the octal string form is accepted
+        Value::Integer(mode) => return Err(Error::runtime(format!("install mode `{}` must be an octal string such as \"0{}\"", mode, mode))),
+        Value::String(mode) => {
+            let mode = mode.to_string_lossy();
+            u32::from_str_radix(mode.trim_start_matches("0o"), 8).map_err(|_| Error::runtime(format!("install mode `{}` is not an octal number", mode)))?
+        }
+        value => {
+            return Err(Error::FromLuaConversionError {
+                from: value.type_name(),
+                to: String::from("Install Mode"),
+                message: Some(String::from("mode must be an octal string such as \"0755\"")),
+            });
+        }
+    };
+
+    if mode > 0o7777 {
+        return Err(Error::runtime(format!("install mode `{:o}` is out of range", mode)));
+    }
+    Ok(mode)
+}
+
+fn install_entry(value: Value) -> Result<InstallEntry> {
+    let table = match value {
+        Value::Table(table) => table,
+        value => return Ok(InstallEntry::Copy(input_path(&value)?)),
+    };
+
+    let component = table.get::<Option<String>>("component").context("component must be a string")?;
+    if let Some(component) = &component
+        && (component.is_empty() || !component.chars().all(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.'))
+    {
+        return Err(Error::runtime(format!("component name `{}` contains invalid characters", component)));
+    }
+
+    if let Some(symlink) = table.get::<Option<PathBuf>>("symlink").context("symlink must be a string")? {
+        if table.contains_key("source")? || table.contains_key("mode")? {
+            return Err(Error::runtime("symlink install entries cannot have a source or mode"));
+        }
+        return Ok(InstallEntry::Symlink { symlink, component });
+    }
+
+    let source = input_path(&table.get::<Value>("source")?).context("source must be a source or an artifact")?;
+    let mode = match table.get::<Value>("mode")? {
+        Value::Nil => None,
+        mode => Some(install_mode(mode)?),
+    };
+
+    if mode.is_none() && component.is_none() {
+        return Ok(InstallEntry::Copy(source));
+    }
+    Ok(InstallEntry::File { source, mode, component })
 }
 
 impl FromLua for ConfigResult {
@@ -40,14 +118,11 @@ impl FromLua for ConfigResult {
             }
         };
 
-        let mut install: HashMap<PathBuf, PathBuf> = HashMap::new();
+        let mut install: HashMap<PathBuf, InstallEntry> = HashMap::new();
         if table.contains_key("install")? {
-            for (k, v) in table
-                .get::<HashMap<PathBuf, UserDataRef<Artifact>>>("install")
-                .context("invalid `install` on config result")?
-                .into_iter()
-            {
-                install.insert(k, v.0.clone());
+            for (k, v) in table.get::<HashMap<PathBuf, Value>>("install").context("invalid `install` on config result")?.into_iter() {
+                let entry = install_entry(v).with_context(|_| format!("invalid `install` entry `{}` on config result", k.to_string_lossy()))?;
+                install.insert(k, entry);
             }
         }
 
@@ -55,7 +130,7 @@ impl FromLua for ConfigResult {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum DepStyle {
     Normal,
     Gcc,
@@ -86,7 +161,7 @@ impl FromLua for DepStyle {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct Rule {
     pub name: String,
     pub description: Option<String>,
@@ -94,6 +169,7 @@ pub struct Rule {
     pub depstyle: DepStyle,
     pub build_compdb: bool,
     pub variables: Vec<String>,
+    pub tools: Vec<PathBuf>,
 }
 
 impl UserData for Rule {
@@ -111,47 +187,13 @@ impl UserData for Rule {
                     return Err(Error::runtime(format!("output name `{}` contains invalid characters", output)));
                 }
 
-                let output = PathBuf::from("output").join(output);
-
-                let inputs_to_paths = |inputs: Vec<Value>| -> Result<Vec<PathBuf>> {
-                    let mut paths: Vec<PathBuf> = Vec::new();
-
-                    for input in inputs {
-                        let userdata = match input.as_userdata() {
-                            None => {
-                                return Err(Error::FromLuaConversionError {
-                                    from: input.type_name(),
-                                    to: String::from("Source or Artifact"),
-                                    message: None,
-                                });
-                            }
-                            Some(userdata) => userdata,
-                        };
-
-                        let mut path: Option<PathBuf> = None;
-
-                        if userdata.is::<Source>() {
-                            path = Some(userdata.borrow::<Source>()?.0.clone());
-                        }
-
-                        if userdata.is::<Artifact>() {
-                            path = Some(userdata.borrow::<Artifact>()?.0.clone());
-                        }
-
-                        if let Some(path) = path {
-                            paths.push(path);
-                            continue;
-                        }
-
-                        return Err(Error::FromLuaConversionError {
-                            from: input.type_name(),
-                            to: String::from("Source or Artifact"),
-                            message: None,
-                        });
-                    }
+                let output_dir = current_project(&appdata.projects).output_dir.clone();
+                let output = output_dir.join(output);
+                if appdata.written_files.borrow().contains(&output) {
+                    return Err(Error::runtime(format!("output `{}` is already written by `fab.write_file`", output.to_string_lossy())));
+                }
 
-                    Ok(paths)
-                };
+                let inputs_to_paths = |inputs: Vec<Value>| -> Result<Vec<PathBuf>> { inputs.iter().map(input_path).collect() };
 
                 let mut final_variables = HashMap::new();
                 for (key, mut value) in variables {
@@ -165,7 +207,7 @@ impl UserData for Rule {
                                 return Err(Error::runtime(format!("depfile `{}` contains invalid characters", value)));
                             }
 
-                            value = PathBuf::from("output").join(value).to_string_lossy().to_string();
+                            value = output_dir.join(value).to_string_lossy().to_string();
                         }
 
                         final_variables.insert(key, value);
@@ -191,6 +233,10 @@ impl UserData for Rule {
                     build.implicit_inputs = Some(inputs_to_paths(implicit_inputs)?);
                 }
 
+                if !rule.tools.is_empty() {
+                    build.implicit_inputs.get_or_insert_default().extend(rule.tools.iter().cloned());
+                }
+
                 appdata.builds.borrow_mut().push(build);
 
                 Ok(Artifact(output))
@@ -199,6 +245,110 @@ impl UserData for Rule {
     }
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum FeatureState {
+    Enabled,
+    Disabled,
+    Auto,
+}
+
+impl FeatureState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FeatureState::Enabled => "enabled",
+            FeatureState::Disabled => "disabled",
+            FeatureState::Auto => "auto",
+        }
+    }
+}
+
+struct Feature {
+    name: String,
+    state: FeatureState,
+}
+
+impl UserData for Feature {
+    fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_, feature| Ok(feature.name.clone()));
+        fields.add_field_method_get("state", |_, feature| Ok(feature.state.as_str()));
+    }
+
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("enabled", |_, feature, ()| Ok(feature.state == FeatureState::Enabled));
+        methods.add_method("disabled", |_, feature, ()| Ok(feature.state == FeatureState::Disabled));
+        methods.add_method("auto", |_, feature, ()| Ok(feature.state == FeatureState::Auto));
+        methods.add_method("allowed", |_, feature, ()| Ok(feature.state != FeatureState::Disabled));
+        methods.add_method("require", |_, feature, (available, reason): (bool, Option<String>)| match feature.state {
+            FeatureState::Disabled => Ok(false),
+            FeatureState::Auto => Ok(available),
+            FeatureState::Enabled => {
+                if !available {
+                    return Err(Error::runtime(format!(
+                        "feature `{}` is enabled but {}",
+                        feature.name,
+                        reason.unwrap_or(String::from("its requirements are not met"))
+                    )));
+                }
+                Ok(true)
+            }
+        });
+    }
+}
+
+fn input_path(input: &Value) -> Result<PathBuf> {
+    if let Some(userdata) = input.as_userdata() {
+        if userdata.is::<Source>() {
+            return Ok(userdata.borrow::<Source>()?.0.clone());
+        }
+
+        if userdata.is::<Artifact>() {
+            return Ok(userdata.borrow::<Artifact>()?.0.clone());
+        }
+    }
+
+    Err(Error::FromLuaConversionError {
+        from: input.type_name(),
+        to: String::from("Source or Artifact"),
+        message: None,
+    })
+}
+
+fn option_value(name: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(str) => Ok(str.to_string_lossy()),
+        Value::Integer(value) => Ok(value.to_string()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Boolean(value) => Ok(value.to_string()),
+        Value::UserData(userdata) if userdata.is::<Feature>() => Ok(userdata.borrow::<Feature>()?.state.as_str().to_string()),
+        Value::Table(table) => {
+            let mut elements = Vec::new();
+            for element in table.sequence_values::<Value>() {
+                match element? {
+                    Value::Table(_) => return Err(Error::runtime(format!("value for option `{}` contains a nested table", name))),
+                    element => elements.push(option_value(name, element)?),
+                }
+            }
+            Ok(elements.join(","))
+        }
+        value => Err(Error::runtime(format!("value for option `{}` cannot be a {}", name, value.type_name()))),
+    }
+}
+
+fn patch_paths(opts: &Table, build_dir: &Path) -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let mut paths = Vec::new();
+    let mut hashes = Vec::new();
+    for patch in opts
+        .get::<Option<Vec<UserDataRef<Source>>>>("patches")
+        .context("patches must be a list of sources")?
+        .unwrap_or_default()
+    {
+        let path = build_dir.join(&patch.0);
+        hashes.push(sha256_file(&path).map_err(|err| Error::runtime(format!("{:#}", err)))?);
+        paths.push(path);
+    }
+    Ok((paths, hashes))
+}
+
 struct Source(PathBuf);
 
 impl UserData for Source {
@@ -223,25 +373,60 @@ pub struct Build {
     pub variables: HashMap<String, String>,
 }
 
-const BUILTIN_VARIABLES: &'static [&'static str] = &["depfile"];
-const RESERVED_VARIABLES: &'static [&'static str] = &["in", "out"];
+pub struct EvaluatedConfig {
+    pub rules: Vec<Rule>,
+    pub builds: Vec<Build>,
+    pub git_dependencies: Vec<GitDependency>,
+    pub archive_dependencies: Vec<ArchiveDependency>,
+    pub tools: Vec<PathBuf>,
+    pub installs: HashMap<PathBuf, InstallEntry>,
+    pub lockfile: Lockfile,
+}
+
+const BUILTIN_VARIABLES: &[&str] = &["depfile"];
+const RESERVED_VARIABLES: &[&str] = &["in", "out"];
 
 pub fn lua_eval_config(
-    project_root: PathBuf,
     build_dir: PathBuf,
     config_path: PathBuf,
     options: HashMap<String, String>,
+    install_dirs: HashMap<String, PathBuf>,
     cache: Option<FabricateCache>,
-    dependency_overrides: HashMap<String, String>,
-) -> Result<(Vec<Rule>, Vec<Build>, Vec<GitDependency>, HashMap<PathBuf, PathBuf>)> {
+    dependency_options: DependencyOptions,
+    machines: Machines,
+) -> Result<EvaluatedConfig> {
+    let project_root = match config_path.parent() {
+        None => return Err(Error::runtime("failed to resolve project root")),
+        Some(project_root) => project_root.to_path_buf(),
+    };
+
     let lua = Lua::new();
 
     let rules: Rc<RefCell<Vec<Rule>>> = Rc::new(RefCell::new(Vec::new()));
     let builds: Rc<RefCell<Vec<Build>>> = Rc::new(RefCell::new(Vec::new()));
+    let written_files: Rc<RefCell<Vec<PathBuf>>> = Rc::new(RefCell::new(Vec::new()));
     let git_deps: Rc<RefCell<Vec<GitDependency>>> = Rc::new(RefCell::new(Vec::new()));
-    let git_overrides: Rc<HashMap<String, String>> = Rc::new(dependency_overrides);
-
-    lua.set_app_data(FabricateAppData { builds: builds.clone() });
+    let archive_deps: Rc<RefCell<Vec<ArchiveDependency>>> = Rc::new(RefCell::new(Vec::new()));
+    let tools: Rc<RefCell<Vec<PathBuf>>> = Rc::new(RefCell::new(Vec::new()));
+    let git_overrides: Rc<HashMap<String, String>> = Rc::new(dependency_options.overrides);
+    let mirrors: Rc<Mirrors> = Rc::new(dependency_options.mirrors);
+    let vendor_dir = dependency_options.vendor_dir.unwrap_or(project_root.join(VENDOR_DIR));
+    let vendor_manifest: Rc<Option<VendorManifest>> = Rc::new(VendorManifest::load(&vendor_dir).map_err(|err| Error::runtime(format!("{:#}", err)))?);
+    let lockfile: Rc<RefCell<Lockfile>> = Rc::new(RefCell::new(Lockfile::load(&project_root).map_err(|err| Error::runtime(format!("{:#}", err)))?));
+    let cached_archives: Rc<Vec<ArchiveDependency>> = Rc::new(cache.as_ref().map(|cache| cache.archive_dependencies.clone()).unwrap_or_default());
+
+    let projects: ProjectStack = Rc::new(RefCell::new(vec![Rc::new(Project {
+        root: project_root.clone(),
+        options,
+        output_dir: PathBuf::from("output"),
+    })]));
+    let subprojects: Rc<RefCell<Vec<PathBuf>>> = Rc::new(RefCell::new(Vec::new()));
+
+    lua.set_app_data(FabricateAppData {
+        builds: builds.clone(),
+        written_files: Rc::clone(&written_files),
+        projects: Rc::clone(&projects),
+    });
 
     lua.load(include_str!("lua/generic.lua")).set_name("=fab_generic").exec()?;
 
@@ -257,11 +442,11 @@ pub fn lua_eval_config(
         })?,
     )?;
     fab_table.set("path_rel", {
-        let project_root = project_root.clone();
+        let projects = Rc::clone(&projects);
         let build_dir = build_dir.clone();
         lua.create_function(move |_, mut path: PathBuf| {
             if path.is_relative() {
-                path = project_root.join(path);
+                path = current_project(&projects).root.join(path);
             }
 
             let path = match diff_paths(&path, &build_dir) {
@@ -273,13 +458,40 @@ pub fn lua_eval_config(
         })?
     })?;
     fab_table.set("project_dir", {
-        let project_dir = project_root.clone();
-        lua.create_function(move |l, ()| Ok(Value::String(l.create_string(project_dir.to_string_lossy().to_string())?)))?
+        let projects = Rc::clone(&projects);
+        lua.create_function(move |l, ()| Ok(Value::String(l.create_string(current_project(&projects).root.to_string_lossy().to_string())?)))?
     })?;
     fab_table.set("build_dir", {
         let build_dir = build_dir.clone();
         lua.create_function(move |l, ()| Ok(Value::String(l.create_string(build_dir.to_string_lossy().to_string())?)))?
     })?;
+    fab_table.set(
+        "install_dir",
+        lua.create_function(move |_, name: String| match install_dirs.get(&name) {
+            None => Err(Error::runtime(format!("unknown installation directory `{}`", name))),
+            Some(dir) => Ok(dir.clone()),
+        })?,
+    )?;
+    fab_table.set(
+        "machine",
+        lua.create_function(move |l, name: Option<String>| {
+            let name = name.unwrap_or(String::from("target"));
+            let machine = match machines.get(&name) {
+                None => return Err(Error::runtime(format!("unknown machine `{}`, expected `host` or `target`", name))),
+                Some(machine) => machine,
+            };
+
+            let table = l.create_table()?;
+            table.set("name", name.as_str())?;
+            table.set("cross", name == "target" && machines.cross)?;
+            table.set("triple", machine.triple.clone())?;
+            table.set("sysroot", machine.sysroot.clone())?;
+            table.set("binaries", machine.binaries.clone())?;
+            table.set("binary_args", machine.binary_args.clone())?;
+            table.set("flags", machine.flags.clone())?;
+            Ok(table)
+        })?,
+    )?;
     fab_table.set(
         "typeof",
         lua.create_function(|_, v: Value| match v {
@@ -296,31 +508,48 @@ pub fn lua_eval_config(
                     return Ok("artifact");
                 }
 
+                if userdata.is::<Feature>() {
+                    return Ok("feature");
+                }
+
                 Ok("unknown")
             }
             _ => Err(Error::runtime("not userdata")),
         })?,
     )?;
-    fab_table.set(
-        "which",
+    fab_table.set("which", {
+        let tools = Rc::clone(&tools);
         lua.create_function(move |_, lookup: String| match which(lookup) {
             Err(which::Error::CannotFindBinaryPath) => Ok(None),
             Err(err) => Err(Error::runtime(err)),
-            Ok(path) => Ok(Some(path)),
-        })?,
-    )?;
-    fab_table.set(
-        "option",
+            Ok(path) => {
+                let mut tools = tools.borrow_mut();
+                if !tools.contains(&path) {
+                    tools.push(path.clone());
+                }
+                Ok(Some(path))
+            }
+        })?
+    })?;
+    fab_table.set("option", {
+        let projects = Rc::clone(&projects);
         lua.create_function(move |l, (name, option_type, required): (String, Value, bool)| {
             if !name.chars().all(|c: char| c.is_alphabetic() || c == '-' || c == '_' || c == '.') {
                 return Err(Error::runtime(format!("option name `{}` contains invalid characters", name)));
             }
 
-            let value = match options.get(&name) {
+            let is_feature = matches!(&option_type, Value::String(str) if str.to_string_lossy() == "feature");
+
+            let project = current_project(&projects);
+            let value = match project.options.get(&name) {
                 None => {
                     if required {
                         return Err(Error::runtime(format!("option `{}` is missing", name)));
                     }
+
+                    if is_feature {
+                        return Ok(Value::UserData(l.create_userdata(Feature { name, state: FeatureState::Auto })?));
+                    }
                     return Ok(Value::Nil);
                 }
                 Some(value) => value,
@@ -329,7 +558,9 @@ pub fn lua_eval_config(
             let error = Error::FromLuaConversionError {
                 from: option_type.type_name(),
                 to: String::from("Option Type"),
-                message: Some(String::from("option type can only be \"string\", \"number\", \"boolean\", or a list of valid string values")),
+                message: Some(String::from(
+                    "option type can only be \"string\", \"number\", \"boolean\", \"feature\", \"array\", or a list of valid string values",
+                )),
             };
 
             match option_type {
@@ -346,7 +577,36 @@ pub fn lua_eval_config(
                         };
                         Ok(Value::Boolean(value))
                     }
-                    _ => return Err(error),
+                    "feature" => {
+                        let state = match value.as_str() {
+                            "enabled" => FeatureState::Enabled,
+                            "disabled" => FeatureState::Disabled,
+                            "auto" => FeatureState::Auto,
+                            _ => {
+                                return Err(Error::runtime(format!(
+                                    "value `{}` for option `{}` is not a feature state, expected one of `enabled`, `disabled`, `auto`",
+                                    value, name
+                                )));
+                            }
+                        };
+                        Ok(Value::UserData(l.create_userdata(Feature { name, state })?))
+                    }
+                    "array" => {
+                        let list = l.create_table()?;
+                        if value.is_empty() {
+                            return Ok(Value::Table(list));
+                        }
+
+                        for element in value.split(',') {
+                            let element = element.trim();
+                            if element.is_empty() {
+                                return Err(Error::runtime(format!("value `{}` for option `{}` contains an empty array element", value, name)));
+                            }
+                            list.push(element)?;
+                        }
+                        Ok(Value::Table(list))
+                    }
+                    _ => Err(error),
                 },
                 Value::Table(table) => {
                     for pair in table.pairs::<Value, Value>() {
@@ -360,21 +620,47 @@ pub fn lua_eval_config(
                             return Ok(v);
                         }
                     }
-                    return Err(Error::runtime(format!("value `{}` for option `{}` is not a valid", value, name)));
+                    Err(Error::runtime(format!("value `{}` for option `{}` is not a valid", value, name)))
                 }
                 _ => Err(error),
             }
-        })?,
-    )?;
+        })?
+    })?;
     fab_table.set("git", {
         let build_dir = build_dir.clone();
         let git_deps_store = Rc::clone(&git_deps);
         let git_overrides = Rc::clone(&git_overrides);
-        lua.create_function(move |_, (name, url, revision): (String, String, String)| {
+        let lockfile = Rc::clone(&lockfile);
+        let mirrors = Rc::clone(&mirrors);
+        let vendor_manifest = Rc::clone(&vendor_manifest);
+        let force = dependency_options.force;
+        let offline = dependency_options.offline;
+        lua.create_function(move |_, (name, url, revision, opts): (String, String, String, Option<Table>)| {
             if !name.chars().all(|c: char| c.is_alphabetic() || c == '-' || c == '_' || c == '.') {
                 return Err(Error::runtime(format!("git dependency name `{}` contains invalid characters", name)));
             }
 
+            let mut git_options = GitOptions {
+                offline,
+                mirrors: Rc::clone(&mirrors),
+                ..GitOptions::default()
+            };
+            let mut patch_hashes = Vec::new();
+            if let Some(opts) = opts {
+                if let Some(depth) = opts.get::<Option<u32>>("depth").context("depth must be a positive integer")? {
+                    if depth == 0 {
+                        return Err(Error::runtime("depth must be a positive integer"));
+                    }
+                    git_options.depth = Some(depth);
+                }
+
+                if let Some(value) = opts.get::<Option<bool>>("submodules").context("submodules must be a boolean")? {
+                    git_options.submodules = value;
+                }
+
+                (git_options.patches, patch_hashes) = patch_paths(&opts, &build_dir)?;
+            }
+
             let mut git_deps = git_deps_store.borrow_mut();
 
             if git_deps.iter().find(|v| v.name == name).is_some() {
@@ -382,62 +668,306 @@ pub fn lua_eval_config(
             }
 
             if let Some(dep_override) = git_overrides.get(&name) {
-                git_deps.push(GitDependency { name, url, revision });
+                git_deps.push(GitDependency {
+                    name,
+                    url,
+                    revision,
+                    commit: None,
+                    submodules: None,
+                    patches: patch_hashes,
+                    head: None,
+                    override_path: Some(PathBuf::from(dep_override)),
+                    vendor_path: None,
+                });
                 return Ok(Artifact(PathBuf::from(dep_override)));
             }
 
             let build_relative_path = PathBuf::from("git").join(&name);
             let repo_path = build_dir.join(&build_relative_path);
 
-            if exists(&repo_path)? {
-                if let Some(cache) = &cache {
-                    if let Some(dep) = cache.git_dependencies.iter().find(|v| v.name == name) {
-                        if dep.url == url && dep.revision == revision {
-                            git_deps.push(GitDependency { name, url, revision });
-                            return Ok(Artifact(build_relative_path));
-                        }
-                    }
+            let mut lockfile = lockfile.borrow_mut();
+            let locked_commit = lockfile.find_git(&name, &url, &revision).map(|locked| locked.commit.clone());
+
+            if let Some(manifest) = vendor_manifest.as_ref()
+                && let Some(vendored) = manifest.git.iter().find(|v| v.name == name)
+            {
+                if vendored.url == url
+                    && vendored.revision == revision
+                    && vendored.submodules == git_options.submodules
+                    && vendored.patches == patch_hashes
+                    && locked_commit.as_ref().is_none_or(|locked_commit| *locked_commit == vendored.commit)
+                {
+                    let vendor_path = manifest.git_path(&name);
+                    lockfile.lock_git(LockedGit {
+                        name: name.clone(),
+                        url: url.clone(),
+                        revision: revision.clone(),
+                        commit: vendored.commit.clone(),
+                    });
+                    git_deps.push(GitDependency {
+                        name,
+                        url,
+                        revision,
+                        commit: Some(vendored.commit.clone()),
+                        submodules: None,
+                        patches: patch_hashes,
+                        head: None,
+                        override_path: None,
+                        vendor_path: Some(vendor_path.clone()),
+                    });
+                    return Ok(Artifact(diff_paths(&vendor_path, &build_dir).unwrap_or(vendor_path)));
+                }
+
+                println!("Warning: vendored git dependency `{}` does not match its declaration, ignoring it", name);
+            }
+
+            let cached_dep = cache.as_ref().and_then(|cache| cache.git_dependencies.iter().find(|v| v.name == name));
+
+            let checkout = if exists(&repo_path)? {
+                if let Some(dep) = cached_dep
+                    && dep.url == url
+                    && dep.revision == revision
+                    && let Some(commit) = &dep.commit
+                    && locked_commit.as_ref().is_none_or(|locked_commit| locked_commit == commit)
+                    && dep.patches == patch_hashes
+                    && !dep.head.as_ref().is_some_and(|head| git::moved_upstream(&repo_path, head))
+                    && dep.submodules.is_some() == git_options.submodules
+                    && dep
+                        .submodules
+                        .as_ref()
+                        .is_none_or(|submodules| git::submodule_state(&repo_path).is_ok_and(|state| state == *submodules))
+                {
+                    lockfile.lock_git(LockedGit {
+                        name: name.clone(),
+                        url: url.clone(),
+                        revision: revision.clone(),
+                        commit: commit.clone(),
+                    });
+                    git_deps.push(GitDependency {
+                        name,
+                        url,
+                        revision,
+                        commit: Some(commit.clone()),
+                        submodules: dep.submodules.clone(),
+                        patches: patch_hashes,
+                        head: dep.head.clone(),
+                        override_path: None,
+                        vendor_path: None,
+                    });
+                    return Ok(Artifact(build_relative_path));
                 }
 
                 println!("Git dependency `{}` outdated, updating...", name);
+                let expected_commit = cached_dep.and_then(|dep| dep.head.as_deref().or(dep.commit.as_deref()));
+                git::update(&mirrors.rewrite(&url), &revision, locked_commit.as_deref(), &repo_path, expected_commit, force, &git_options)
             } else {
                 println!("Git dependency `{}` not found, cloning...", name);
+                git::clone(&mirrors.rewrite(&url), &revision, locked_commit.as_deref(), &repo_path, &git_options)
             }
+            .map_err(|err| Error::runtime(format!("Failed to prepare git dependency `{}`: {:#}", name, err)))?;
 
-            if exists(&repo_path)? {
-                remove_dir_all(&repo_path)?;
+            let submodules = match git_options.submodules {
+                false => None,
+                true => Some(git::submodule_state(&repo_path).map_err(|err| Error::runtime(format!("Failed to prepare git dependency `{}`: {:#}", name, err)))?),
+            };
+
+            lockfile.lock_git(LockedGit {
+                name: name.clone(),
+                url: url.clone(),
+                revision: revision.clone(),
+                commit: checkout.commit.clone(),
+            });
+            git_deps.push(GitDependency {
+                name,
+                url,
+                revision,
+                commit: Some(checkout.commit),
+                submodules,
+                patches: patch_hashes,
+                head: Some(checkout.head),
+                override_path: None,
+                vendor_path: None,
+            });
+
+            Ok(Artifact(build_relative_path))
+        })?
+    })?;
+    fab_table.set("archive", {
+        let build_dir = build_dir.clone();
+        let archive_deps_store = Rc::clone(&archive_deps);
+        let git_overrides = Rc::clone(&git_overrides);
+        let cached_archives = Rc::clone(&cached_archives);
+        let mirrors = Rc::clone(&mirrors);
+        let vendor_manifest = Rc::clone(&vendor_manifest);
+        let offline = dependency_options.offline;
+        lua.create_function(move |_, (name, url, sha256, opts): (String, String, String, Option<Table>)| {
+            if !name.chars().all(|c: char| c.is_alphabetic() || c == '-' || c == '_' || c == '.') {
+                return Err(Error::runtime(format!("archive dependency name `{}` contains invalid characters", name)));
             }
 
-            let fetch_opts = FetchOptions::new();
+            if !archive::is_sha256(&sha256) {
+                return Err(Error::runtime(format!("sha256 of archive dependency `{}` is not a valid sha256 hash", name)));
+            }
 
-            let mut builder = RepoBuilder::new();
-            builder.fetch_options(fetch_opts);
+            let mut format = ArchiveFormat::from_url(&url);
+            let mut strip_components = 0;
+            let mut patches = Vec::new();
+            let mut patch_hashes = Vec::new();
+            if let Some(opts) = opts {
+                if let Some(value) = opts.get::<Option<String>>("format").context("format must be a string")? {
+                    format = match ArchiveFormat::from_name(&value) {
+                        None => return Err(Error::runtime(format!("invalid archive format `{}`, must be one of tar, tar.gz, tar.xz or zip", value))),
+                        Some(format) => Some(format),
+                    };
+                }
 
-            let repo = builder
-                .clone(url.as_str(), repo_path.as_ref())
-                .map_err(|err| Error::runtime(format!("Git clone failed for git dependency `{}`: {}", name, err)))?;
+                if let Some(value) = opts.get::<Option<u32>>("strip_components").context("strip_components must be a non-negative integer")? {
+                    strip_components = value;
+                }
 
-            let obj = repo
-                .revparse_single(revision.as_str())
-                .map_err(|err| Error::runtime(format!("Failed to resolve revision for git dependency `{}`: {}", name, err)))?;
-            let commit = obj
-                .peel_to_commit()
-                .map_err(|err| Error::runtime(format!("Failed to resolve commit of revision for git dependency `{}`: {}", name, err)))?;
+                (patches, patch_hashes) = patch_paths(&opts, &build_dir)?;
+            }
 
-            repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().force()))
-                .map_err(|err| Error::runtime(format!("Failed to checkout revision for git dependency `{}`: {}", name, err)))?;
+            let format = match format {
+                None => return Err(Error::runtime(format!("unable to determine the archive format of `{}`, pass the format option", url))),
+                Some(format) => format,
+            };
 
-            repo.set_head_detached(commit.id())
-                .map_err(|err| Error::runtime(format!("Failed to set head for git dependency `{}`: {}", name, err)))?;
+            let mut archive_deps = archive_deps_store.borrow_mut();
 
-            git_deps.push(GitDependency { name, url, revision });
+            if archive_deps.iter().any(|v| v.name == name) {
+                return Err(Error::runtime(format!("archive dependency defined twice `{}`", name)));
+            }
+
+            let dep = ArchiveDependency {
+                name,
+                url,
+                sha256: sha256.to_lowercase(),
+                strip_components,
+                patches: patch_hashes,
+                override_path: None,
+                vendor_path: None,
+            };
+
+            if let Some(dep_override) = git_overrides.get(&dep.name) {
+                archive_deps.push(ArchiveDependency {
+                    override_path: Some(PathBuf::from(dep_override)),
+                    ..dep
+                });
+                return Ok(Artifact(PathBuf::from(dep_override)));
+            }
+
+            if let Some(manifest) = vendor_manifest.as_ref()
+                && let Some(vendored) = manifest.archives.iter().find(|v| v.name == dep.name)
+            {
+                if vendored.url == dep.url && vendored.sha256 == dep.sha256 && vendored.strip_components == dep.strip_components && vendored.patches == dep.patches {
+                    let vendor_path = manifest.archive_path(&dep.name);
+                    archive_deps.push(ArchiveDependency {
+                        vendor_path: Some(vendor_path.clone()),
+                        ..dep
+                    });
+                    return Ok(Artifact(diff_paths(&vendor_path, &build_dir).unwrap_or(vendor_path)));
+                }
+
+                println!("Warning: vendored archive dependency `{}` does not match its declaration, ignoring it", dep.name);
+            }
+
+            let build_relative_path = PathBuf::from("archives").join(&dep.name);
+            let archive_path = build_dir.join(&build_relative_path);
+
+            if exists(&archive_path)? {
+                if cached_archives.contains(&dep) {
+                    archive_deps.push(dep);
+                    return Ok(Artifact(build_relative_path));
+                }
+
+                println!("Archive dependency `{}` outdated, downloading...", dep.name);
+            } else {
+                println!("Archive dependency `{}` not found, downloading...", dep.name);
+            }
+
+            let fetch_url = mirrors.rewrite(&dep.url);
+            if offline && !is_local_url(&fetch_url) {
+                return Err(Error::runtime(format!(
+                    "Failed to prepare archive dependency `{}`: archive is not available locally and `--offline` prevents downloading it from `{}`",
+                    dep.name, fetch_url
+                )));
+            }
+
+            archive::fetch(&fetch_url, &dep.sha256, format, dep.strip_components, &patches, &archive_path)
+                .map_err(|err| Error::runtime(format!("Failed to prepare archive dependency `{}`: {:#}", dep.name, err)))?;
+
+            archive_deps.push(dep);
 
             Ok(Artifact(build_relative_path))
         })?
     })?;
+    fab_table.set("dependency", {
+        let build_dir = build_dir.clone();
+        let projects = Rc::clone(&projects);
+        let subprojects = Rc::clone(&subprojects);
+        lua.create_function(move |l, (name, path, opts): (String, Value, Option<Table>)| {
+            if !name.chars().all(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.') {
+                return Err(Error::runtime(format!("dependency name `{}` contains invalid characters", name)));
+            }
+
+            let parent = current_project(&projects);
+            let output_dir = parent.output_dir.join(&name);
+            if subprojects.borrow().contains(&output_dir) {
+                return Err(Error::runtime(format!("dependency `{}` is defined twice", name)));
+            }
+
+            let root = match &path {
+                Value::String(str) => parent.root.join(str.to_string_lossy()),
+                path => build_dir.join(input_path(path)?),
+            };
+            let root = root
+                .canonicalize()
+                .map_err(|err| Error::runtime(format!("failed to resolve directory of dependency `{}`: {}", name, err)))?;
+
+            if projects.borrow().iter().any(|project| project.root == root) {
+                return Err(Error::runtime(format!("dependency `{}` is a project that is already being evaluated", name)));
+            }
+
+            let config_path = root.join("fab.lua");
+            if !config_path.is_file() {
+                return Err(Error::runtime(format!("dependency `{}` has no fab.lua in `{}`", name, root.to_string_lossy())));
+            }
+
+            let mut options = HashMap::new();
+            if let Some(opts) = opts
+                && let Some(values) = opts.get::<Option<Table>>("options").context("options must be a table")?
+            {
+                for pair in values.pairs::<String, Value>() {
+                    let (key, value) = pair?;
+                    let value = option_value(&key, value)?;
+                    options.insert(key, value);
+                }
+            }
+
+            subprojects.borrow_mut().push(output_dir.clone());
+
+            let package = l.globals().get::<Table>("package")?;
+            let package_path = package.get::<String>("path")?;
+            package.set("path", format!("{};{}/?/fab.lua", package_path, root.to_string_lossy()))?;
+
+            let env = l.create_table()?;
+            let env_meta = l.create_table()?;
+            env_meta.set("__index", l.globals())?;
+            env.set_metatable(Some(env_meta))?;
+
+            projects.borrow_mut().push(Rc::new(Project { root, options, output_dir }));
+            let result = l.load(config_path).set_environment(env).eval::<Value>();
+            projects.borrow_mut().pop();
+
+            package.set("path", package_path)?;
+
+            result.with_context(|_| format!("failed to evaluate dependency `{}`", name))
+        })?
+    })?;
     fab_table.set("glob", {
         let build_dir = build_dir.clone();
-        let project_root = project_root.clone();
+        let projects = Rc::clone(&projects);
         lua.create_function(move |_, mut args: Variadic<Value>| {
             let opts: Option<Table> = match args.last() {
                 Some(Value::Table(_)) => match args.pop() {
@@ -447,13 +977,13 @@ pub fn lua_eval_config(
                 _ => None,
             };
 
-            if args.len() == 0 {
+            if args.is_empty() {
                 return Err(Error::runtime("no globs in glob call"));
             }
 
             let mut case_sensitive = false;
             let mut require_literal_separator = false;
-            let mut relative_to = project_root.to_path_buf();
+            let mut relative_to = current_project(&projects).root.clone();
             if let Some(opts) = opts {
                 if let Some(value) = opts.get::<Option<bool>>("case_sensitive").context("case_sensitive must be a boolean")? {
                     case_sensitive = value;
@@ -525,8 +1055,11 @@ pub fn lua_eval_config(
         let build_dir = build_dir.clone();
         let git_overrides = Rc::clone(&git_overrides);
         let git_deps = Rc::clone(&git_deps);
-        let project_root = project_root.clone();
+        let archive_deps = Rc::clone(&archive_deps);
+        let vendor_manifest = Rc::clone(&vendor_manifest);
+        let projects = Rc::clone(&projects);
         lua.create_function(move |_, str: String| {
+            let project_root = current_project(&projects).root.clone();
             let full_path = project_root
                 .join(&str)
                 .canonicalize()
@@ -543,6 +1076,22 @@ pub fn lua_eval_config(
                 break;
             }
 
+            for dep in archive_deps.borrow().iter() {
+                let dep_path = build_dir.join("archives").join(&dep.name);
+                if !full_path.starts_with(dep_path) {
+                    continue;
+                }
+
+                found_in_dep = true;
+                break;
+            }
+
+            if let Some(manifest) = vendor_manifest.as_ref()
+                && full_path.starts_with(&manifest.dir)
+            {
+                found_in_dep = true;
+            }
+
             if !found_in_dep {
                 for (_, v) in git_overrides.iter() {
                     if !full_path.starts_with(v) {
@@ -572,13 +1121,70 @@ pub fn lua_eval_config(
             Ok(Source(path))
         })?
     })?;
+    fab_table.set("write_file", {
+        let build_dir = build_dir.clone();
+        let builds = Rc::clone(&builds);
+        let written_files = Rc::clone(&written_files);
+        let projects = Rc::clone(&projects);
+        lua.create_function(move |_, (name, content): (String, mlua::String)| {
+            if !name.chars().all(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.') {
+                return Err(Error::runtime(format!("file name `{}` contains invalid characters", name)));
+            }
+
+            let output_dir = current_project(&projects).output_dir.clone();
+            let output = output_dir.join(&name);
+            if builds.borrow().iter().any(|build| build.output == output) {
+                return Err(Error::runtime(format!("file `{}` is already the output of a build", name)));
+            }
+            if written_files.borrow().contains(&output) {
+                return Err(Error::runtime(format!("file `{}` is already written by `fab.write_file`", name)));
+            }
+
+            // Keep the file untouched when the content did not change so that dependents are not rebuilt
+            let path = build_dir.join(&output);
+            let content = content.as_bytes();
+            if read(&path).ok().as_deref() != Some(&content[..]) {
+                create_dir_all(build_dir.join(&output_dir)).map_err(|err| Error::runtime(format!("failed to create output directory: {}", err)))?;
+                write(&path, &content[..]).map_err(|err| Error::runtime(format!("failed to write file `{}`: {}", name, err)))?;
+            }
+
+            written_files.borrow_mut().push(output.clone());
+            Ok(Artifact(output))
+        })?
+    })?;
     fab_table.set("def_rule", {
         let rule_store = Rc::clone(&rules);
-        lua.create_function(move |_, (name, command, description, depstyle, build_compdb): (String, String, _, _, _)| {
+        lua.create_function(move |_, (name, command, description, depstyle, build_compdb): (String, Value, _, _, _)| {
             if name.is_empty() {
                 return Err(Error::runtime("empty rule name"));
             }
 
+            let mut tools = Vec::new();
+            let command = match command {
+                Value::String(command) => command.to_string_lossy(),
+                Value::Table(parts) => {
+                    let mut components = Vec::new();
+                    for part in parts.sequence_values::<Value>() {
+                        match part? {
+                            Value::String(part) => components.push(part.to_string_lossy()),
+                            part => {
+                                let path = input_path(&part)?;
+                                components.push(path.to_string_lossy().to_string());
+                                tools.push(path);
+                            }
+                        }
+                    }
+                    components.join(" ")
+                }
+                command => {
+                    return Err(Error::FromLuaConversionError {
+                        from: command.type_name(),
+                        to: String::from("Rule Command"),
+                        message: Some(String::from("command can only be a string or a list of strings, sources and artifacts")),
+                    });
+                }
+            };
+
             if command.is_empty() {
                 return Err(Error::runtime("empty rule command"));
             }
@@ -595,10 +1201,10 @@ pub fn lua_eval_config(
 
                 variables.borrow_mut().push(var.clone());
 
-                return format!("$fabvar_{}", var);
+                format!("$fabvar_{}", var)
             };
 
-            let var_regex = Regex::new(r"@(.+?)@").map_err(|err| Error::runtime(err))?;
+            let var_regex = Regex::new(r"@(.+?)@").map_err(Error::runtime)?;
             let command = var_regex.replace_all(&command, &var_parse).to_string();
 
             let mut description: Option<String> = description;
@@ -610,7 +1216,7 @@ pub fn lua_eval_config(
                 return Err(Error::runtime("rule that begin with `fab_` are reserved"));
             }
 
-            if !name.chars().all(|c: char| c.is_alphabetic() || c == '-' || c == '_' || c == '.') {
+            if !name.chars().all(|c: char| c.is_alphanumeric() || c == '-' || c == '_' || c == '.') {
                 return Err(Error::runtime(format!("rule name `{}` contains invalid characters", name)));
             }
 
@@ -621,9 +1227,18 @@ pub fn lua_eval_config(
                 depstyle,
                 build_compdb,
                 variables: variables.into_inner(),
+                tools,
             };
 
-            rule_store.borrow_mut().push(rule.clone());
+            let mut rule_store = rule_store.borrow_mut();
+            if let Some(existing) = rule_store.iter().find(|existing| existing.name == rule.name) {
+                if *existing != rule {
+                    return Err(Error::runtime(format!("rule `{}` is defined twice with conflicting definitions", rule.name)));
+                }
+                return Ok(rule);
+            }
+
+            rule_store.push(rule.clone());
 
             Ok(rule)
         })?
@@ -643,13 +1258,13 @@ pub fn lua_eval_config(
         let name = m.0;
         let source = m.1.to_owned();
 
-        let loader = lua.create_function(move |l, ()| l.load(&source).set_name(&format!("={}", name)).eval::<mlua::Value>())?;
+        let loader = lua.create_function(move |l, ()| l.load(&source).set_name(format!("={}", name)).eval::<mlua::Value>())?;
 
         lua.preload_module(name, loader)?;
     }
 
     let package = globals.get::<Table>("package")?;
-    package.set("path", format!("{};{}/?/fab.lua", package.get::<String>("path")?, project_root.to_string_lossy().to_string()))?;
+    package.set("path", format!("{};{}/?/fab.lua", package.get::<String>("path")?, project_root.to_string_lossy()))?;
 
     let result = lua.load(config_path).eval::<ConfigResult>()?;
 
@@ -658,6 +1273,157 @@ pub fn lua_eval_config(
     let rules = Rc::try_unwrap(rules).map_err(|_| Error::runtime("failed to collect rules"))?.into_inner();
     let builds = Rc::try_unwrap(builds).map_err(|_| Error::runtime("failed to collect builds"))?.into_inner();
     let git_deps = Rc::try_unwrap(git_deps).map_err(|_| Error::runtime("failed to collect git_deps"))?.into_inner();
+    let archive_deps = Rc::try_unwrap(archive_deps).map_err(|_| Error::runtime("failed to collect archive_deps"))?.into_inner();
+    let tools = Rc::try_unwrap(tools).map_err(|_| Error::runtime("failed to collect tools"))?.into_inner();
+    let mut lockfile = Rc::try_unwrap(lockfile).map_err(|_| Error::runtime("failed to collect lockfile"))?.into_inner();
+
+    lockfile.retain_git(&git_deps.iter().map(|dep| dep.name.as_str()).collect::<Vec<&str>>());
+
+    Ok(EvaluatedConfig {
+        rules,
+        builds,
+        git_dependencies: git_deps,
+        archive_dependencies: archive_deps,
+        tools,
+        installs: result.install,
+        lockfile,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs::{create_dir_all, read_to_string, write},
+        path::{Path, PathBuf},
+    };
+
+    use mlua::{Lua, Value};
+
+    use super::{EvaluatedConfig, install_mode, lua_eval_config};
+    use crate::{
+        cache::InstallEntry,
+        deps::{DependencyOptions, mirrors::Mirrors},
+        install::resolve_install_dirs,
+        machine::{Machine, Machines},
+        testing::TestDir,
+    };
+
+    fn eval(name: &str, config: &str) -> (TestDir, mlua::Result<EvaluatedConfig>) {
+        let project_dir = TestDir::new(name);
+        let build_dir = project_dir.join("build");
+        create_dir_all(&build_dir).unwrap();
+        write(project_dir.join("fab.lua"), config).unwrap();
+
+        let result = lua_eval_config(
+            build_dir,
+            project_dir.join("fab.lua"),
+            HashMap::new(),
+            resolve_install_dirs("/usr", Vec::new()).unwrap(),
+            None,
+            DependencyOptions {
+                overrides: HashMap::new(),
+                force: false,
+                offline: true,
+                mirrors: Mirrors::default(),
+                vendor_dir: None,
+            },
+            // A fixed machine keeps the results independent of the toolchain variables of the environment
+            Machines::native(Machine::default()),
+        );
+        (project_dir, result)
+    }
+
+    #[test]
+    fn pkg_config_with_symlink_entries() {
+        let (project_dir, result) = eval(
+            "pkg-config",
+            r#"
+local lang_c = require("lang_c")
+local cp = fab.def_rule("cp", "cp @IN@ @OUT@")
+local libk = cp:build("libk.so.1", { fab.def_source("fab.lua") }, {})
+
+local install = {}
+for i = 1, 8 do
+    install["lib/libk" .. i .. ".so"] = { symlink = "libk.so.1" }
+end
+install["lib64/libk.so.1"] = libk
+
+lang_c.generate_pkg_config(install, { name = "libk", version = "1.0", library = libk, requires = { "libc" } })
+return { install = install }
+"#,
+        );
+        let config = result.unwrap();
+
+        let pc = read_to_string(project_dir.join("build/output/libk.pc")).unwrap();
+        assert_eq!(
+            pc,
+            "prefix=/usr\nlibdir=${prefix}/lib64\nincludedir=${prefix}/include\n\nName: libk\nDescription: libk\nVersion: 1.0\nRequires: libc\nLibs: -L${libdir} -lk\nCflags: -I${includedir}\n"
+        );
+        assert!(matches!(
+            config.installs.get(&PathBuf::from("/usr/lib/pkgconfig/libk.pc")),
+            Some(InstallEntry::Copy(source)) if source == Path::new("output/libk.pc")
+        ));
+        assert_eq!(config.installs.len(), 10);
+    }
 
-    Ok((rules, builds, git_deps, result.install))
+    #[test]
+    fn write_file_conflicts_with_build_output() {
+        let build_then_write = r#"
+local cp = fab.def_rule("cp", "cp @IN@ @OUT@")
+cp:build("gen.txt", { fab.def_source("fab.lua") }, {})
+fab.write_file("gen.txt", "content")
+"#;
+        let write_then_build = r#"
+fab.write_file("gen.txt", "content")
+local cp = fab.def_rule("cp", "cp @IN@ @OUT@")
+cp:build("gen.txt", { fab.def_source("fab.lua") }, {})
+"#;
+        let write_twice = r#"
+fab.write_file("gen.txt", "content")
+fab.write_file("gen.txt", "other content")
+"#;
+
+        for (name, config, message) in [
+            ("build-then-write", build_then_write, "file `gen.txt` is already the output of a build"),
+            ("write-then-build", write_then_build, "output `output/gen.txt` is already written by `fab.write_file`"),
+            ("write-twice", write_twice, "file `gen.txt` is already written by `fab.write_file`"),
+        ] {
+            let (_, result) = eval(name, config);
+            let err = result.err().unwrap_or_else(|| panic!("{} should fail", name));
+            assert_eq!(err.to_string().lines().next(), Some(format!("runtime error: {}", message).as_str()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn install_mode_parsing() {
+        let lua = Lua::new();
+        let string = |mode: &str| Value::String(lua.create_string(mode).unwrap());
+
+        assert_eq!(install_mode(string("755")).unwrap(), 0o755);
+        assert_eq!(install_mode(string("0755")).unwrap(), 0o755);
+        assert_eq!(install_mode(string("0o644")).unwrap(), 0o644);
+        assert_eq!(install_mode(string("4755")).unwrap(), 0o4755);
+
+        assert!(install_mode(Value::Integer(755)).is_err());
+        assert!(install_mode(string("10000")).is_err());
+        assert!(install_mode(string("789")).is_err());
+        assert!(install_mode(string("")).is_err());
+        assert!(install_mode(Value::Boolean(true)).is_err());
+    }
+
+    #[test]
+    fn toolchain_instance_names() {
+        let (_, result) = eval(
+            "toolchain-instance",
+            r#"
+assert(toolchain_instance_name == nil)
+assert(fab.toolchain_instance_name("cc", "gcc") == "cc")
+assert(fab.toolchain_instance_name("cc", "clang") == "cc_2")
+assert(fab.toolchain_instance_name("cc", "gcc") == "cc")
+assert(fab.toolchain_instance_name("ld", "clang") == "ld")
+"#,
+        );
+        result.unwrap();
+    }
 }
@@ -29,6 +29,36 @@ function path(...)
     return fab.path_join(...)
 end
 
+local toolchain_instances = {}
+
+--- Get a unique name for an instance of a toolchain.
+--- Instances with the same identity (for example the command they run) share a name.
+--- @param name string Base name of the toolchain
+--- @param identity string Identity of the instance
+--- @return string
+function fab.toolchain_instance_name(name, identity)
+    local instances = toolchain_instances[name] or {}
+    toolchain_instances[name] = instances
+
+    local index = nil
+    for i, instance in ipairs(instances) do
+        if instance == identity then
+            index = i
+            break
+        end
+    end
+
+    if index == nil then
+        table.insert(instances, identity)
+        index = #instances
+    end
+
+    if index == 1 then
+        return name
+    end
+    return name .. "_" .. index
+end
+
 --- Get an artifact name for a given source file.
 --- @param source Source
 --- @return string
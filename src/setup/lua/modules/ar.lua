@@ -1,12 +1,22 @@
 local mod = {}
 
-local function get_ar_generic(ar_type, path)
+local binaries = { llvm_ar = "llvm-ar", ar = "ar" }
+
+local function get_ar_generic(ar_type, path, machine, args)
+    local command = { path }
+    table.extend(command, args or {})
+    table.extend(command, machine.flags.ar or {})
+
+    local name = fab.toolchain_instance_name("ar_" .. ar_type .. "_" .. machine.name, table.join(command, " "))
+
     --- @class Ar
-    --- @field rule Rule
+    --- @field machine Machine
+    --- @field create_rule Rule
     local Ar = {
+        machine = machine,
         create_rule = fab.def_rule(
-            "ar_" .. ar_type .. "_create",
-            path .. " rcs @OUT@ @IN@",
+            name .. "_create",
+            table.join(command, " ") .. " rcs @OUT@ @IN@",
             "Creating archive @OUT@ from @IN@"
         )
     }
@@ -23,22 +33,54 @@ local function get_ar_generic(ar_type, path)
 end
 
 --- Get an AR object.
+--- Without a type or path the archiver configured for the machine is preferred.
+--- On cross machines GNU ar is looked up with the target triple as prefix.
 --- @param ar_type ("llvm_ar" | "ar")?
 --- @param path string?
+--- @param machine ("host" | "target" | Machine)? Machine to archive for (default: target)
 --- @return Ar?
-function mod.get_ar(ar_type, path)
-    local lookup = { "llvm_ar", "ar" }
+function mod.get_ar(ar_type, path, machine)
+    if type(machine) ~= "table" then
+        machine = fab.machine(machine)
+    end
+
+    if path == nil and ar_type == nil and machine.binaries.ar ~= nil then
+        local binary = machine.binaries.ar
+        local ar_path = fab.which(binary)
+        if ar_path == nil then
+            error("Archiver `" .. binary .. "` configured for the " .. machine.name .. " machine was not found")
+        end
+
+        ar_type = binary:match("[^/]*$"):find("llvm") and "llvm_ar" or "ar"
+        return get_ar_generic(ar_type, ar_path, machine, machine.binary_args.ar)
+    end
 
     if path ~= nil then
-        lookup = { path }
-    elseif ar_type ~= nil then
+        local ar_path = fab.which(path)
+        if ar_path == nil then
+            return nil
+        end
+
+        if ar_type == nil then
+            ar_type = path:match("[^/]*$"):find("llvm") and "llvm_ar" or "ar"
+        end
+        return get_ar_generic(ar_type, ar_path, machine)
+    end
+
+    local lookup = { "llvm_ar", "ar" }
+    if ar_type ~= nil then
         lookup = { ar_type }
     end
 
     for _, ar in ipairs(lookup) do
-        path = fab.which(ar)
-        if path ~= nil then
-            return get_ar_generic(ar_type or ar, path)
+        local binary = binaries[ar] or ar
+        if ar == "ar" and machine.triple ~= nil then
+            binary = machine.triple .. "-ar"
+        end
+
+        local ar_path = fab.which(binary)
+        if ar_path ~= nil then
+            return get_ar_generic(ar, ar_path, machine)
         end
     end
 
@@ -1,26 +1,70 @@
 local mod = {}
 
-local function get_gnu_compiler(compiler_type, path)
-    path = fab.which(path or compiler_type)
+local function shell_quote(str)
+    return "'" .. str:gsub("'", "'\\''") .. "'"
+end
+
+local function run_check(command)
+    return os.execute(command .. " >/dev/null 2>&1") == true
+end
+
+local function get_gnu_compiler(compiler_type, path, machine, args)
+    if type(machine) ~= "table" then
+        machine = fab.machine(machine)
+    end
+
+    if path == nil then
+        path = compiler_type
+        if compiler_type == "gcc" and machine.triple ~= nil then
+            path = machine.triple .. "-gcc"
+        end
+    end
+
+    path = fab.which(path)
 
     if path == nil then
         return nil
     end
 
+    local machine_args = {}
+    if compiler_type == "clang" and machine.triple ~= nil then
+        table.insert(machine_args, "--target=" .. machine.triple)
+    end
+    if machine.sysroot ~= nil then
+        table.insert(machine_args, "--sysroot=" .. machine.sysroot)
+    end
+
+    local compile_command = { path }
+    table.extend(compile_command, args or {})
+    table.extend(compile_command, machine_args)
+    table.extend(compile_command, machine.flags.c or {})
+
+    local link_command = { path }
+    table.extend(link_command, args or {})
+    table.extend(link_command, machine_args)
+    table.extend(link_command, machine.flags.c_link or {})
+
+    local name = fab.toolchain_instance_name(
+        "compiler_" .. compiler_type .. "_" .. machine.name,
+        table.join(compile_command, " ") .. "\n" .. table.join(link_command, " ")
+    )
+
     --- @class CCompiler
+    --- @field machine Machine
     --- @field compile_rule Rule
     --- @field link_rule Rule
     local CCompiler = {
+        machine = machine,
         compile_rule = fab.def_rule(
-            "compiler_" .. compiler_type .. "_compile",
-            path .. " -MD -MF @DEPFILE@ -MQ @OUT@ @ARGS@ -c -o @OUT@ @IN@",
+            name .. "_compile",
+            table.join(compile_command, " ") .. " -MD -MF @DEPFILE@ -MQ @OUT@ @ARGS@ -c -o @OUT@ @IN@",
             "Compiling C object @OUT@ from @IN@",
             compiler_type,
             true
         ),
         link_rule = fab.def_rule(
-            "compiler_" .. compiler_type .. "_link",
-            path .. " @ARGS@ -o @OUT@ @IN@",
+            name .. "_link",
+            table.join(link_command, " ") .. " @ARGS@ -o @OUT@ @IN@",
             "Linking C objects @IN@ to @OUT@"
         )
     }
@@ -96,34 +140,104 @@ local function get_gnu_compiler(compiler_type, path)
         return self:link(artifact, self:generate(sources, args or {}, include_dirs), args or {}, linker_script, implicit_inputs)
     end
 
+    --- Check whether a snippet of C code compiles at setup time.
+    --- @param code string
+    --- @param args string[]?
+    --- @param feature Feature? Feature that requires the check to pass
+    --- @return boolean
+    function CCompiler:compiles(code, args, feature)
+        if feature ~= nil and feature:disabled() then
+            return false
+        end
+
+        local source = os.tmpname()
+        local file = assert(io.open(source, "w"))
+        file:write(code)
+        file:close()
+
+        local ok = run_check(table.join(compile_command, " ") .. " -x c -fsyntax-only " .. table.join(args or {}, " ") .. " " .. shell_quote(source))
+        os.remove(source)
+
+        if feature == nil then
+            return ok
+        end
+        return feature:require(ok, "a compile check failed using " .. tostring(self))
+    end
+
+    --- Check whether a header can be included at setup time.
+    --- @param header string
+    --- @param args string[]?
+    --- @param feature Feature? Feature that requires the header
+    --- @return boolean
+    function CCompiler:has_header(header, args, feature)
+        if feature ~= nil and feature:disabled() then
+            return false
+        end
+
+        local ok = self:compiles("#include <" .. header .. ">\n", args)
+
+        if feature == nil then
+            return ok
+        end
+        return feature:require(ok, "header `" .. header .. "` was not found")
+    end
+
     setmetatable(CCompiler, {
-        __tostring = function(self) return "CCompiler(" .. compiler_type .. ", " .. path .. ")" end
+        __tostring = function(self) return "CCompiler(" .. compiler_type .. ", " .. machine.name .. ", " .. path .. ")" end
     })
 
     return CCompiler
 end
 
 --- Get a clang compiler.
+--- Clang is passed the target triple of cross machines.
 --- @param path string? Optional full path to a clang binary
+--- @param machine ("host" | "target" | Machine)? Machine to compile for (default: target)
 --- @return CCompiler?
-function mod.get_clang(path)
-    return get_gnu_compiler("clang", path)
+function mod.get_clang(path, machine)
+    return get_gnu_compiler("clang", path, machine)
 end
 
 --- Get a GCC compiler.
+--- On cross machines GCC is looked up with the target triple as prefix.
 --- @param path string? Optional full path to a gcc binary
+--- @param machine ("host" | "target" | Machine)? Machine to compile for (default: target)
 --- @return CCompiler?
-function mod.get_gcc(path)
-    return get_gnu_compiler("gcc", path)
+function mod.get_gcc(path, machine)
+    return get_gnu_compiler("gcc", path, machine)
 end
 
 --- Get a any C compiler.
+--- Prefers the C compiler configured for the machine.
+--- @param machine ("host" | "target" | Machine)? Machine to compile for (default: target)
 --- @return CCompiler?
-function mod.get_compiler()
+function mod.get_compiler(machine)
+    if type(machine) ~= "table" then
+        machine = fab.machine(machine)
+    end
+
+    local binary = machine.binaries.c
+    if binary ~= nil then
+        -- A wrapper such as ccache is followed by the actual compiler
+        local args = machine.binary_args.c or {}
+        local compiler_type = "gcc"
+        for _, word in ipairs({ binary, table.unpack(args) }) do
+            if not word:find("^-") and word:match("[^/]*$"):find("clang") then
+                compiler_type = "clang"
+            end
+        end
+
+        local compiler = get_gnu_compiler(compiler_type, binary, machine, args)
+        if compiler == nil then
+            error("C compiler `" .. binary .. "` configured for the " .. machine.name .. " machine was not found")
+        end
+        return compiler
+    end
+
     local compiler_fns = { mod.get_clang, mod.get_gcc }
 
     for _, compiler_fn in ipairs(compiler_fns) do
-        local compiler = compiler_fn()
+        local compiler = compiler_fn(nil, machine)
         if compiler ~= nil then
             return compiler
         end
@@ -132,6 +246,156 @@ function mod.get_compiler()
     return nil
 end
 
+--- Look up a library using pkg-config.
+--- @param name string Package name
+--- @param feature Feature? Feature that requires the package
+--- @return CPackage?
+function mod.pkg_config(name, feature)
+    if feature ~= nil and feature:disabled() then
+        return nil
+    end
+
+    local pkg_config = fab.which("pkg-config")
+    local found = pkg_config ~= nil and run_check(pkg_config .. " --exists " .. shell_quote(name))
+
+    if feature ~= nil then
+        found = feature:require(found, "package `" .. name .. "` was not found by pkg-config")
+    end
+
+    if not found then
+        return nil
+    end
+
+    local function query(flag)
+        local handle = assert(io.popen(pkg_config .. " " .. flag .. " " .. shell_quote(name)))
+        local output = handle:read("a")
+        handle:close()
+        return string.split(output)
+    end
+
+    --- @class CPackage
+    --- @field name string
+    --- @field cflags string[]
+    --- @field libs string[]
+    local CPackage = {
+        name = name,
+        cflags = query("--cflags"),
+        libs = query("--libs")
+    }
+
+    setmetatable(CPackage, {
+        __tostring = function(self) return "CPackage(" .. self.name .. ")" end
+    })
+
+    return CPackage
+end
+
+local function pkg_config_path(path)
+    local prefix = fab.install_dir("prefix")
+    if not path:starts_with("/") then
+        path = fab.path_join(prefix, path)
+    end
+
+    if path == prefix then
+        return "${prefix}"
+    end
+    if path:starts_with(prefix .. "/") then
+        return "${prefix}" .. path:sub(#prefix + 1)
+    end
+    return path
+end
+
+local function pkg_config_names(packages)
+    local names = {}
+    for _, package in ipairs(packages or {}) do
+        table.insert(names, type(package) == "table" and package.name or package)
+    end
+    return table.join(names, ", ")
+end
+
+--- Generate a pkg-config file for an installed library and add it to the install table.
+--- The library directory is taken from the install table entry of the library if there is one.
+--- Valid options:
+--- - name: string Package name, also used as the file name
+--- - description: string?
+--- - version: string?
+--- - url: string?
+--- - library: Artifact? Library that consumers link against
+--- - include_dirs: string[]? Installed include directories (default: { includedir })
+--- - requires: (string | CPackage)[]?
+--- - requires_private: (string | CPackage)[]?
+--- - cflags: string[]?
+--- - libs: string[]?
+--- - libs_private: string[]?
+--- - install_dir: string? Directory the file is installed to (default: libdir/pkgconfig)
+--- - component: string? Install component of the file
+--- @param install table Install table that is returned from fab.lua
+--- @param opts table
+--- @return Artifact
+function mod.generate_pkg_config(install, opts)
+    assert(type(opts.name) == "string", "pkg-config file requires a name")
+
+    local libdir = fab.install_dir("libdir")
+    local lib_flags = {}
+    if opts.library ~= nil then
+        for dest, entry in pairs(install) do
+            local source = type(entry) == "table" and entry.source or entry
+            if type(source) == "userdata" and fab.typeof(source) == "artifact" and source.path == opts.library.path then
+                dest = dest:starts_with("/") and dest or fab.path_join(fab.install_dir("prefix"), dest)
+                libdir = dest:match("^(.*)/[^/]*$")
+                break
+            end
+        end
+
+        local file_name = opts.library.path:match("[^/]*$")
+        local link_name = file_name:match("^lib(.-)%.so[%.%d]*$") or file_name:match("^lib(.-)%.[^%.]+$")
+        table.insert(lib_flags, "-L${libdir}")
+        table.insert(lib_flags, link_name ~= nil and "-l" .. link_name or "-l:" .. file_name)
+    end
+    table.extend(lib_flags, opts.libs or {})
+
+    local include_dirs = opts.include_dirs or { fab.install_dir("includedir") }
+    local cflags = {}
+    for i, include_dir in ipairs(include_dirs) do
+        table.insert(cflags, i == 1 and "-I${includedir}" or "-I" .. pkg_config_path(include_dir))
+    end
+    table.extend(cflags, opts.cflags or {})
+
+    local lines = {
+        "prefix=" .. fab.install_dir("prefix"),
+        "libdir=" .. pkg_config_path(libdir),
+    }
+    if #include_dirs > 0 then
+        table.insert(lines, "includedir=" .. pkg_config_path(include_dirs[1]))
+    end
+    table.insert(lines, "")
+    table.insert(lines, "Name: " .. opts.name)
+    table.insert(lines, "Description: " .. (opts.description or opts.name))
+    table.insert(lines, "Version: " .. (opts.version or "0"))
+
+    local fields = {
+        { "URL", opts.url },
+        { "Requires", pkg_config_names(opts.requires) },
+        { "Requires.private", pkg_config_names(opts.requires_private) },
+        { "Libs", table.join(lib_flags, " ") },
+        { "Libs.private", table.join(opts.libs_private or {}, " ") },
+        { "Cflags", table.join(cflags, " ") },
+    }
+    for _, field in ipairs(fields) do
+        if field[2] ~= nil and field[2] ~= "" then
+            table.insert(lines, field[1] .. ": " .. field[2])
+        end
+    end
+
+    local file = fab.write_file(opts.name .. ".pc", table.join(lines, "\n") .. "\n")
+
+    local dest = fab.path_join(opts.install_dir or fab.path_join(fab.install_dir("libdir"), "pkgconfig"), opts.name .. ".pc")
+    assert(install[dest] == nil, "install destination `" .. dest .. "` is already used")
+    install[dest] = { source = file, component = opts.component }
+
+    return file
+end
+
 --- Create an include directory object.
 --- @param path string
 --- @return CIncludeDir
@@ -1,23 +1,46 @@
 local mod = {}
 
 --- Get a NASM assembler object.
+--- Without a path the assembler configured for the machine is preferred.
 --- @param path string? Optional full path to a nasm binary
+--- @param machine ("host" | "target" | Machine)? Machine to assemble for (default: target)
 --- @return NASM?
-function mod.get_nasm(path)
-    path = fab.which(path or "nasm")
+function mod.get_nasm(path, machine)
+    if type(machine) ~= "table" then
+        machine = fab.machine(machine)
+    end
+
+    local args = {}
+    if path == nil and machine.binaries.nasm ~= nil then
+        path = fab.which(machine.binaries.nasm)
+        if path == nil then
+            error("Assembler `" .. machine.binaries.nasm .. "` configured for the " .. machine.name .. " machine was not found")
+        end
+        args = machine.binary_args.nasm or {}
+    else
+        path = fab.which(path or "nasm")
+    end
 
     if path == nil then
         return nil
     end
 
+    local command = { path }
+    table.extend(command, args)
+    table.extend(command, machine.flags.nasm or {})
+
+    local name = fab.toolchain_instance_name("assembler_nasm_" .. machine.name, table.join(command, " "))
+
     --- @class NASM
     --- @field name string
+    --- @field machine Machine
     --- @field rule Rule
     local Nasm = {
         name = "nasm",
+        machine = machine,
         rule = fab.def_rule(
-            "assembler_nasm_assemble",
-            path .. " @ARGS@ -MD @DEPFILE@ -MQ @OUT@ -o @OUT@ @IN@",
+            name .. "_assemble",
+            table.join(command, " ") .. " @ARGS@ -MD @DEPFILE@ -MQ @OUT@ -o @OUT@ @IN@",
             "Assembling @IN@ from @OUT@",
             "gcc",
             true
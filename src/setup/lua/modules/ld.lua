@@ -1,12 +1,23 @@
 local mod = {}
 
-local function get_linker_generic(linker_type, path)
+local function get_linker_generic(linker_type, path, machine, args)
+    local command = { path }
+    table.extend(command, args or {})
+    if machine.sysroot ~= nil then
+        table.insert(command, "--sysroot=" .. machine.sysroot)
+    end
+    table.extend(command, machine.flags.ld or {})
+
+    local name = fab.toolchain_instance_name("linker_" .. linker_type .. "_" .. machine.name, table.join(command, " "))
+
     --- @class Linker
+    --- @field machine Machine
     --- @field rule Rule
     local Linker = {
+        machine = machine,
         rule = fab.def_rule(
-            "linker_" .. linker_type .. "_link",
-            path .. " -o @OUT@ @ARGS@ @IN@",
+            name .. "_link",
+            table.join(command, " ") .. " -o @OUT@ @ARGS@ @IN@",
             "Linking @IN@ to @OUT@"
         )
     }
@@ -38,22 +49,54 @@ local function get_linker_generic(linker_type, path)
 end
 
 --- Get a linker object.
+--- Without a type or path the linker configured for the machine is preferred.
+--- On cross machines GNU ld is looked up with the target triple as prefix.
 --- @param linker_type ("ld.lld" | "ld")?
 --- @param path string?
+--- @param machine ("host" | "target" | Machine)? Machine to link for (default: target)
 --- @return Linker?
-function mod.get_linker(linker_type, path)
-    local lookup = { "ld.lld", "ld" }
+function mod.get_linker(linker_type, path, machine)
+    if type(machine) ~= "table" then
+        machine = fab.machine(machine)
+    end
+
+    if path == nil and linker_type == nil and machine.binaries.ld ~= nil then
+        local binary = machine.binaries.ld
+        local linker_path = fab.which(binary)
+        if linker_path == nil then
+            error("Linker `" .. binary .. "` configured for the " .. machine.name .. " machine was not found")
+        end
+
+        linker_type = binary:match("[^/]*$"):find("lld") and "ld.lld" or "ld"
+        return get_linker_generic(linker_type, linker_path, machine, machine.binary_args.ld)
+    end
 
     if path ~= nil then
-        lookup = { path }
-    elseif linker_type ~= nil then
+        local linker_path = fab.which(path)
+        if linker_path == nil then
+            return nil
+        end
+
+        if linker_type == nil then
+            linker_type = path:match("[^/]*$"):find("lld") and "ld.lld" or "ld"
+        end
+        return get_linker_generic(linker_type, linker_path, machine)
+    end
+
+    local lookup = { "ld.lld", "ld" }
+    if linker_type ~= nil then
         lookup = { linker_type }
     end
 
     for _, linker in ipairs(lookup) do
-        path = fab.which(linker)
-        if path ~= nil then
-            return get_linker_generic(linker_type or linker, path)
+        local binary = linker
+        if linker == "ld" and machine.triple ~= nil then
+            binary = machine.triple .. "-ld"
+        end
+
+        local linker_path = fab.which(binary)
+        if linker_path ~= nil then
+            return get_linker_generic(linker, linker_path, machine)
         end
     end
 
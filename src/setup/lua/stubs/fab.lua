@@ -30,9 +30,21 @@ function fab.project_dir() end
 --- @return string
 function fab.build_dir() end
 
+--- Get an absolute path to an installation directory, such as "prefix", "bindir" or "libdir".
+--- @param name string
+--- @return string
+function fab.install_dir(name) end
+
+--- Get a unique name for an instance of a toolchain.
+--- Instances with the same identity (for example the command they run) share a name.
+--- @param name string Base name of the toolchain
+--- @param identity string Identity of the instance
+--- @return string
+function fab.toolchain_instance_name(name, identity) end
+
 --- Retrieve the fab type of userdata.
 --- @param value userdata
---- @return "unknown" | "source" | "rule"
+--- @return "unknown" | "source" | "rule" | "artifact" | "feature"
 function fab.typeof(value) end
 
 --- Find an executable binary’s path by name.
@@ -43,18 +55,50 @@ function fab.typeof(value) end
 --- @return string? Absolute path to the executable
 function fab.which(lookup) end
 
+--- Describe the host (running the build) or target (running the build output) machine.
+--- @param name "host" | "target"
+--- @return Machine
+function fab.machine(name) end
+
 --- Clones a git repository into the build directory.
+--- The resolved commit is pinned in `fab.lock`.
+--- A table of options can be passed in as the last argument. Valid options:
+--- - depth: integer
+--- - submodules: boolean
+--- - patches: Source[]
 --- @param name string
 --- @param url string
 --- @param revision string
+--- @param opts table?
+--- @return Artifact
+function fab.git(name, url, revision, opts) end
+
+--- Downloads and extracts an archive into the build directory.
+--- A table of options can be passed in as the last argument. Valid options:
+--- - format: "tar" | "tar.gz" | "tar.xz" | "zip"
+--- - strip_components: integer
+--- - patches: Source[]
+--- @param name string
+--- @param url string
+--- @param sha256 string
+--- @param opts table?
 --- @return Artifact
-function fab.git(name, url, revision) end
+function fab.archive(name, url, sha256, opts) end
+
+--- Evaluates the fab.lua of another project as a subproject and returns the value it returns.
+--- A table of options can be passed in as the last argument. Valid options:
+--- - options: { [string]: string | number | boolean | Feature | string[] }
+--- @param name string
+--- @param path string | Source | Artifact
+--- @param opts table?
+--- @return any
+function fab.dependency(name, path, opts) end
 
 --- Declare an option that can be passed by the user to fabricate.
 --- @param name string
---- @param type "string" | "number" | "boolean" | string[]
+--- @param type "string" | "number" | "boolean" | "feature" | "array" | string[]
 --- @param required boolean?
---- @return string | number | boolean | nil
+--- @return string | number | boolean | Feature | string[] | nil
 function fab.option(name, type, required) end
 
 --- Define a [Source](lua://Source).
@@ -62,9 +106,16 @@ function fab.option(name, type, required) end
 --- @return Source
 function fab.def_source(path) end
 
+--- Write a file into the output directory at setup time and return it as an artifact.
+--- The file is only rewritten when its content changes.
+--- @param name string
+--- @param content string
+--- @return Artifact
+function fab.write_file(name, content) end
+
 --- Define a [Rule](lua://Rule).
 --- @param name string
---- @param command string
+--- @param command string | (string | Source | Artifact)[]
 --- @param description string?
 --- @param depstyle ("normal" | "gcc" | "clang" | "msvc")?
 --- @param compdb boolean?
@@ -19,3 +19,41 @@ Rule = {}
 --- @param implicit_inputs (Source | Artifact)[]? Implicit inputs.
 --- @return Artifact
 function Rule:build(output, input, variables, implicit_inputs) end
+
+--- @class (exact) Machine
+--- @field name "host" | "target" Name of the machine.
+--- @field cross boolean Whether the machine is described by a cross file.
+--- @field triple string? Target triple.
+--- @field sysroot string? Absolute path to the sysroot.
+--- @field binaries { [string]: string } Tools configured for the machine.
+--- @field binary_args { [string]: string[] } Leading arguments of the configured tools.
+--- @field flags { [string]: string[] } Default arguments per tool.
+Machine = {}
+
+--- @class (exact) Feature
+--- @field name string Name of the option that declared the feature.
+--- @field state "enabled" | "disabled" | "auto" State of the feature.
+Feature = {}
+
+--- Whether the feature was explicitly enabled.
+--- @return boolean
+function Feature:enabled() end
+
+--- Whether the feature was explicitly disabled.
+--- @return boolean
+function Feature:disabled() end
+
+--- Whether the feature is left to automatic detection.
+--- @return boolean
+function Feature:auto() end
+
+--- Whether the feature is not disabled.
+--- @return boolean
+function Feature:allowed() end
+
+--- Resolve the feature against the availability of its requirements.
+--- Errors if the feature is enabled but the requirements are not available.
+--- @param available boolean Whether the requirements are available.
+--- @param reason string? Explanation used in the error message.
+--- @return boolean
+function Feature:require(available, reason) end
@@ -1,6 +1,6 @@
 use std::{
     collections::HashMap,
-    env::set_current_dir,
+    env::{current_exe, set_current_dir},
     fs::{create_dir_all, write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
@@ -10,14 +10,41 @@ use anyhow::{Context, Result, bail};
 use which::which;
 
 use crate::{
-    cache::FabricateCache,
-    setup::{lua::lua_eval_config, ninja::build_ninja_file},
+    cache::{CACHE_FILE, FabricateCache, Tool},
+    deps::DependencyOptions,
+    install::{InstallOptions, resolve_install_dirs},
+    machine::{ENVIRONMENT_VARIABLES, Machine, Machines, environment, load_cross_file},
+    setup::{
+        lua::lua_eval_config,
+        ninja::{build_install_targets, build_ninja_file},
+    },
 };
 
 mod lua;
 mod ninja;
 
-pub fn setup(config_file: String, build_dir: String, prefix: String, options: Vec<(String, String)>, dependency_overrides: Vec<(String, String)>) -> Result<()> {
+pub fn resolve_config(config_file: &str) -> Result<(PathBuf, PathBuf)> {
+    let config_file = Path::new(config_file).canonicalize().context("Failed to resolve config path")?;
+    if config_file.is_dir() {
+        return Ok((config_file.join("fab.lua"), config_file));
+    }
+
+    let config_dir = match config_file.parent() {
+        None => bail!("Failed to resolve config directory"),
+        Some(config_dir) => config_dir.to_path_buf(),
+    };
+
+    Ok((config_file, config_dir))
+}
+
+pub fn setup(
+    config_file: String,
+    build_dir: PathBuf,
+    install_options: InstallOptions,
+    options: Vec<(String, String)>,
+    dependency_options: DependencyOptions,
+    cross_file: Option<String>,
+) -> Result<()> {
     let ninja_path = match which("ninja") {
         Err(err) => {
             println!("Warning: failed to locate ninja, cannot cleanup or generate compdb ({})", err);
@@ -27,23 +54,25 @@ pub fn setup(config_file: String, build_dir: String, prefix: String, options: Ve
     };
 
     // Resolve config
-    let mut config_file = Path::new(&config_file).canonicalize().context("Failed to resolve config path")?;
-    let config_dir: PathBuf;
-    if config_file.is_dir() {
-        config_dir = config_file;
-        config_file = config_dir.join("fab.lua")
-    } else {
-        config_dir = match config_file.parent() {
-            None => bail!("Failed to resolve config directory"),
-            Some(config_dir) => config_dir.to_path_buf(),
-        };
-    }
+    let (config_file, config_dir) = resolve_config(&config_file)?;
+
+    let install_dirs = resolve_install_dirs(&install_options.prefix, install_options.dirs)?;
+
+    // Load cross file
+    let cross_file = match cross_file {
+        None => None,
+        Some(cross_file) => Some(Path::new(&cross_file).canonicalize().context("Failed to resolve cross file path")?),
+    };
+
+    let environment = environment();
+    let host = Machine::from_environment(&environment);
+
+    let machines = match &cross_file {
+        None => Machines::native(host),
+        Some(cross_file) => Machines::cross(host, load_cross_file(cross_file)?),
+    };
 
     // Create build dir
-    let mut build_dir = PathBuf::from(build_dir);
-    if build_dir.is_relative() {
-        build_dir = config_dir.join("build");
-    }
     create_dir_all(&build_dir).context("Failed to create build directory")?;
 
     set_current_dir(&build_dir).with_context(|| format!("Failed to change working directory to the build directory `{}`", build_dir.to_string_lossy()))?;
@@ -52,44 +81,86 @@ pub fn setup(config_file: String, build_dir: String, prefix: String, options: Ve
     write(build_dir.join(".gitignore"), "# Generated by Fabricate\n*").context("Failed to write .gitignore")?;
 
     // Load cache
-    let cache_path = build_dir.join("fabricate_cache.toml");
+    let cache_path = build_dir.join(CACHE_FILE);
     let cache = FabricateCache::load(&cache_path)?;
 
-    // Cleanup build directory
-    if let Some(ninja_path) = &ninja_path {
-        if cache.is_some() {
-            let mut cleanup_cmd = Command::new(ninja_path);
-            if let Err(err) = cleanup_cmd.arg("-C").arg(&build_dir).arg("-t").arg("cleandead").status() {
-                println!("Warning: ninja cleanup failed ({})", err);
+    // Compare environment
+    if let Some(cache) = &cache {
+        for name in ENVIRONMENT_VARIABLES {
+            let previous = cache.environment.get(*name);
+            let current = environment.get(*name);
+            if previous == current {
+                continue;
             }
+
+            let describe = |value: Option<&String>| match value {
+                None => String::from("unset"),
+                Some(value) => format!("`{}`", value),
+            };
+
+            println!(
+                "Warning: environment variable `{}` changed since the last setup ({} -> {}), affected builds will be rebuilt",
+                name,
+                describe(previous),
+                describe(current)
+            );
+        }
+    }
+
+    // Cleanup build directory
+    if let Some(ninja_path) = &ninja_path
+        && cache.is_some()
+    {
+        let mut cleanup_cmd = Command::new(ninja_path);
+        if let Err(err) = cleanup_cmd.arg("-C").arg(&build_dir).arg("-t").arg("cleandead").status() {
+            println!("Warning: ninja cleanup failed ({})", err);
         }
     }
 
     // Evaluate lua config
-    let (rules, builds, git_deps, installs) = match lua_eval_config(
-        config_dir.to_path_buf(),
+    let relative_config_file = config_file.strip_prefix(&config_dir).ok().map(Path::to_path_buf);
+    let config = match lua_eval_config(
         build_dir.to_path_buf(),
         config_file,
         HashMap::from_iter(options.clone()),
+        install_dirs.clone(),
         cache,
-        HashMap::from_iter(dependency_overrides.clone()),
+        dependency_options,
+        machines,
     ) {
         Err(err) => bail!("{}", err),
         Ok(config) => config,
     };
 
     // Update cache
-    let cache = FabricateCache::new(&cache_path, prefix, HashMap::from_iter(options), installs, git_deps);
+    let mut cache = FabricateCache::new(&cache_path, config_dir, install_options.prefix);
+    cache.config_file = relative_config_file;
+    cache.install_dirs = install_dirs;
+    cache.options = HashMap::from_iter(options);
+    cache.cross_file = cross_file;
+    cache.environment = environment;
+    cache.installs = config.installs;
+    cache.git_dependencies = config.git_dependencies;
+    cache.archive_dependencies = config.archive_dependencies;
+    cache.tools = config.tools.into_iter().map(|path| Tool { path }).collect();
 
     cache.update()?;
 
+    // Update lockfile
+    config.lockfile.update()?;
+
     // Build ninja file
-    write(build_dir.join("build.ninja"), build_ninja_file(&rules, &builds)).context("Failed to write ninja build file")?;
+    let mut ninja_data = build_ninja_file(&config.rules, &config.builds);
+    if install_options.targets {
+        let fabricate = current_exe().context("Failed to resolve the fabricate executable path")?;
+        ninja_data.push_str(&build_install_targets(&fabricate, &build_dir, &config.builds, &cache.installs));
+    }
+    write(build_dir.join("build.ninja"), ninja_data).context("Failed to write ninja build file")?;
 
     // Build compdb
     if let Some(ninja_path) = ninja_path {
         let mut compdb_rules = Vec::new();
-        for rule in rules {
+        for rule in config.rules {
             if !rule.build_compdb {
                 continue;
             }
@@ -97,7 +168,7 @@ pub fn setup(config_file: String, build_dir: String, prefix: String, options: Ve
             compdb_rules.push(rule.name.clone());
         }
 
-        if compdb_rules.len() > 0 {
+        if !compdb_rules.is_empty() {
             let mut compdb_cmd = Command::new(ninja_path);
             let output = match compdb_cmd.arg("-C").arg(&build_dir).arg("-t").arg("compdb").stdout(Stdio::piped()).output() {
                 Err(err) => {
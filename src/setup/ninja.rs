@@ -1,4 +1,12 @@
-use crate::setup::lua::{Build, DepStyle, Rule};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cache::InstallEntry,
+    setup::lua::{Build, DepStyle, Rule},
+};
 
 fn ninja_escape(mut str: String, escape_var: bool) -> String {
     if escape_var {
@@ -54,5 +62,51 @@ pub fn build_ninja_file(rules: &Vec<Rule>, builds: &Vec<Build>) -> String {
         ninja_data.push('\n');
     }
 
-    return ninja_data;
+    ninja_data
+}
+
+fn shell_quote(str: &str) -> String {
+    format!("'{}'", str.replace('\'', "'\\''"))
+}
+
+pub fn build_install_targets(fabricate: &Path, build_dir: &Path, builds: &[Build], installs: &HashMap<PathBuf, InstallEntry>) -> String {
+    let mut ninja_data = String::new();
+
+    ninja_data.push_str("# Install Targets\n");
+    ninja_data.push_str("rule fabricate_install\n");
+    ninja_data.push_str(
+        format!(
+            "    command = {} --build-dir {} install $args\n",
+            ninja_escape(shell_quote(&fabricate.to_string_lossy()), true),
+            ninja_escape(shell_quote(&build_dir.to_string_lossy()), true)
+        )
+        .as_str(),
+    );
+    ninja_data.push_str("    description = Installing $what\n");
+    ninja_data.push_str("    pool = console\n\n");
+
+    // Without a default target ninja would also build the install targets
+    let outputs: Vec<String> = builds.iter().map(|build| ninja_escape(build.output.to_string_lossy().to_string(), true)).collect();
+    ninja_data.push_str(format!("build all: phony {}\n", outputs.join(" ")).as_str());
+    ninja_data.push_str("default all\n\n");
+
+    let mut components: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for entry in installs.values() {
+        let inputs = components.entry(entry.component()).or_default();
+        if let Some(source) = entry.source() {
+            inputs.insert(ninja_escape(source.to_string_lossy().to_string(), true));
+        }
+    }
+
+    let all_inputs: BTreeSet<String> = components.values().flatten().cloned().collect();
+    ninja_data.push_str(format!("build install: fabricate_install {}\n", Vec::from_iter(all_inputs).join(" ")).as_str());
+    ninja_data.push_str("    what = all components\n\n");
+
+    for (component, inputs) in components {
+        ninja_data.push_str(format!("build install-{}: fabricate_install {}\n", component, Vec::from_iter(inputs).join(" ")).as_str());
+        ninja_data.push_str(format!("    args = --component {}\n", component).as_str());
+        ninja_data.push_str(format!("    what = component {}\n\n", component).as_str());
+    }
+
+    ninja_data
 }